@@ -0,0 +1,358 @@
+// compares the queries the book listings used to run for every book with the joined query they run now
+// it creates, seeds and drops its own database on the server, so any account allowed to create databases works:
+//   DATABASE_URL=mysql://root@localhost cargo run --release --example book_listing
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use sqlx::{
+    mysql::{MySqlConnectOptions, MySqlPoolOptions},
+    MySql, Pool, QueryBuilder,
+};
+
+// the app's own row type and grouping, so the joined query is measured decoding what the listings decode
+#[path = "../src/book_rows.rs"]
+mod book_rows;
+#[allow(dead_code)]
+#[path = "../src/db_structs.rs"]
+mod db_structs;
+
+use book_rows::{books_query, group_books, BookRow};
+use db_structs::{AutorLivro, Livro};
+
+const FIXTURE_DATABASE: &str = "libra_hub_book_listing";
+const BOOKS: i32 = 1000;
+const AUTHORS: i32 = 200;
+const AUTHORS_PER_BOOK: i32 = 3;
+const COPIES_PER_BOOK: i32 = 2;
+// rows per INSERT while seeding, well under the limit of bound parameters
+const BATCH: usize = 500;
+const ROLES: [&str; 4] = ["autor", "tradutor", "ilustrador", "editor"];
+
+const PAGE_SIZE: i32 = 50;
+const PAGES: i32 = 20;
+const ROUNDS: u32 = 5;
+
+// only the tables and columns the listings read, with the keys the app's migrations give them
+const FIXTURE_SCHEMA: &[&str] = &[
+    "CREATE TABLE `editoras` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `categorias` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `sub_categorias` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_categoria` int(11) NOT NULL,
+  `nome` varchar(255) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `FK_sub_categoria_categorias` (`id_categoria`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `autores` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `livros` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  `resumo` longtext DEFAULT NULL,
+  `n_paginas` int(11) NOT NULL,
+  `idioma` varchar(255) NOT NULL,
+  `img_url` text DEFAULT NULL,
+  `ano_edicao` varchar(255) DEFAULT NULL,
+  `id_editora` int(11) NOT NULL,
+  `id_sub_categoria` int(11) DEFAULT NULL,
+  `isbn10` varchar(10) DEFAULT NULL,
+  `isbn13` varchar(13) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `livros_id_editora` (`id_editora`),
+  KEY `livros_ibfk_5` (`id_sub_categoria`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `livros_autores` (
+  `id_livro` int(11) NOT NULL,
+  `id_autor` int(11) NOT NULL,
+  `papel` varchar(32) NOT NULL DEFAULT 'autor',
+  `ordem` int(11) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`id_livro`, `id_autor`, `papel`),
+  KEY `livros_autores_id_autor` (`id_autor`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `exemplares` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_livro` int(11) NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `exemplares_id_livro` (`id_livro`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE `requisicoes` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_livro_requisitado` int(11) NOT NULL,
+  `id_exemplar` int(11) DEFAULT NULL,
+  `data_entrega` datetime DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `requisicoes_id_livro_requisitado` (`id_livro_requisitado`),
+  KEY `requisicoes_id_exemplar` (`id_exemplar`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+];
+
+// every book has AUTHORS_PER_BOOK credits and COPIES_PER_BOOK copies, every third one has a copy on loan
+// and every tenth one has no sub-category, so all the joins of the listing query have rows to go through
+async fn seed(conn: &Pool<MySql>) -> Result<(), sqlx::Error> {
+    for statement in FIXTURE_SCHEMA {
+        sqlx::query(statement).execute(conn).await?;
+    }
+
+    sqlx::query(
+        "INSERT INTO editoras (id, nome) VALUES (1, 'Porto Editora'), (2, 'Bertrand'), (3, 'Leya')",
+    )
+    .execute(conn)
+    .await?;
+    sqlx::query("INSERT INTO categorias (id, nome) VALUES (1, 'Ficção'), (2, 'Ciência')")
+        .execute(conn)
+        .await?;
+    sqlx::query(
+        "INSERT INTO sub_categorias (id, id_categoria, nome) VALUES (1, 1, 'Romance'), (2, 1, 'Policial'), (3, 2, 'Física')",
+    )
+    .execute(conn)
+    .await?;
+
+    let authors: Vec<i32> = (1..=AUTHORS).collect();
+
+    for batch in authors.chunks(BATCH) {
+        QueryBuilder::<MySql>::new("INSERT INTO autores (id, nome) ")
+            .push_values(batch, |mut row, id| {
+                row.push_bind(id).push_bind(format!("Autor {}", id));
+            })
+            .build()
+            .execute(conn)
+            .await?;
+    }
+
+    let books: Vec<i32> = (1..=BOOKS).collect();
+
+    for batch in books.chunks(BATCH) {
+        QueryBuilder::<MySql>::new(
+            "INSERT INTO livros (id, nome, resumo, n_paginas, idioma, ano_edicao, id_editora, id_sub_categoria, isbn13) ",
+        )
+        .push_values(batch, |mut row, id| {
+            row.push_bind(id)
+                .push_bind(format!("Livro {}", id))
+                .push_bind("Um resumo com o tamanho de uma contracapa. ".repeat(8))
+                .push_bind(100 + id % 400)
+                .push_bind("Português")
+                .push_bind((1950 + id % 70).to_string())
+                .push_bind(id % 3 + 1)
+                .push_bind((id % 10 != 0).then_some(id % 3 + 1))
+                .push_bind(format!("978{:010}", id));
+        })
+        .build()
+        .execute(conn)
+        .await?;
+
+        let credits: Vec<(i32, i32)> = batch
+            .iter()
+            .flat_map(|id| (0..AUTHORS_PER_BOOK).map(move |order| (*id, order)))
+            .collect();
+
+        QueryBuilder::<MySql>::new(
+            "INSERT INTO livros_autores (id_livro, id_autor, papel, ordem) ",
+        )
+        .push_values(&credits, |mut row, (id, order)| {
+            row.push_bind(id)
+                .push_bind((id * AUTHORS_PER_BOOK + order) % AUTHORS + 1)
+                .push_bind(ROLES[*order as usize % ROLES.len()])
+                .push_bind(order);
+        })
+        .build()
+        .execute(conn)
+        .await?;
+
+        let copies: Vec<i32> = batch
+            .iter()
+            .flat_map(|id| (0..COPIES_PER_BOOK).map(move |_| *id))
+            .collect();
+
+        QueryBuilder::<MySql>::new("INSERT INTO exemplares (id_livro) ")
+            .push_values(&copies, |mut row, id| {
+                row.push_bind(id);
+            })
+            .build()
+            .execute(conn)
+            .await?;
+    }
+
+    // one returned loan per book and an open one on the first copy of every third book
+    sqlx::query(
+        "INSERT INTO requisicoes (id_livro_requisitado, id_exemplar, data_entrega)
+        SELECT id_livro, MIN(id), NOW() FROM exemplares GROUP BY id_livro",
+    )
+    .execute(conn)
+    .await?;
+    sqlx::query(
+        "INSERT INTO requisicoes (id_livro_requisitado, id_exemplar)
+        SELECT id_livro, MIN(id) FROM exemplares WHERE id_livro % 3 = 0 GROUP BY id_livro",
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+// one query for the page and then the authors, category, sub-category, publisher, copies and loans of each book
+async fn per_book_queries(offset: i32, conn: &Pool<MySql>) -> Result<(usize, usize), sqlx::Error> {
+    let books: Vec<Livro> = sqlx::query_as("SELECT * FROM livros ORDER BY id LIMIT ? OFFSET ?")
+        .bind(PAGE_SIZE)
+        .bind(offset)
+        .fetch_all(conn)
+        .await?;
+
+    let mut queries = 1;
+
+    for book in &books {
+        sqlx::query_as::<_, AutorLivro>(
+            "SELECT autores.id, autores.nome, livros_autores.papel FROM livros_autores
+            INNER JOIN autores ON autores.id = livros_autores.id_autor
+            WHERE livros_autores.id_livro = ?
+            ORDER BY livros_autores.ordem",
+        )
+        .bind(book.id)
+        .fetch_all(conn)
+        .await?;
+
+        sqlx::query_scalar::<_, String>(
+            "SELECT nome FROM categorias WHERE id = (SELECT id_categoria FROM sub_categorias WHERE id = ?)",
+        )
+        .bind(book.id_sub_categoria)
+        .fetch_optional(conn)
+        .await?;
+
+        sqlx::query_scalar::<_, Option<String>>("SELECT nome FROM sub_categorias WHERE id = ?")
+            .bind(book.id_sub_categoria)
+            .fetch_optional(conn)
+            .await?;
+
+        sqlx::query_scalar::<_, String>("SELECT nome FROM editoras WHERE id = ?")
+            .bind(book.id_editora)
+            .fetch_one(conn)
+            .await?;
+
+        sqlx::query_as::<_, (i64, i64)>(
+            "SELECT COUNT(*), CAST(COALESCE(SUM(NOT EXISTS(SELECT * FROM requisicoes WHERE requisicoes.id_exemplar = exemplares.id AND requisicoes.data_entrega IS NULL)), 0) AS SIGNED)
+            FROM exemplares WHERE id_livro = ?",
+        )
+        .bind(book.id)
+        .fetch_one(conn)
+        .await?;
+
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL",
+        )
+        .bind(book.id)
+        .fetch_one(conn)
+        .await?;
+
+        queries += 6;
+    }
+
+    Ok((books.len(), queries))
+}
+
+// what fetch_books does for a page of the catalogue, without the pool of the app
+async fn joined_query(offset: i32, conn: &Pool<MySql>) -> Result<(usize, usize), sqlx::Error> {
+    let query = books_query("SELECT * FROM livros ORDER BY id LIMIT ? OFFSET ?");
+
+    let rows: Vec<BookRow> = sqlx::query_as(&query)
+        .bind(PAGE_SIZE)
+        .bind(offset)
+        .fetch_all(conn)
+        .await?;
+
+    Ok((group_books(rows).len(), 1))
+}
+
+fn report(name: &str, elapsed: Duration, books: usize, queries: usize) {
+    let pages = (PAGES as u32 * ROUNDS) as f64;
+
+    println!(
+        "{:<20} {:>10.2} ms/página {:>8.1} consultas/página {:>6} livros",
+        name,
+        elapsed.as_secs_f64() * 1000.0 / pages,
+        queries as f64 / pages,
+        books / ROUNDS as usize
+    );
+}
+
+async fn benchmark(pool: &Pool<MySql>) -> Result<(), sqlx::Error> {
+    // one untimed pass so both strategies start with a warm buffer pool
+    for page in 0..PAGES {
+        per_book_queries(page * PAGE_SIZE, pool).await?;
+        joined_query(page * PAGE_SIZE, pool).await?;
+    }
+
+    let (mut per_book, mut joined) = ((Duration::ZERO, 0, 0), (Duration::ZERO, 0, 0));
+
+    for _ in 0..ROUNDS {
+        for page in 0..PAGES {
+            let start = Instant::now();
+            let (books, queries) = per_book_queries(page * PAGE_SIZE, pool).await?;
+            per_book = (
+                per_book.0 + start.elapsed(),
+                per_book.1 + books,
+                per_book.2 + queries,
+            );
+
+            let start = Instant::now();
+            let (books, queries) = joined_query(page * PAGE_SIZE, pool).await?;
+            joined = (
+                joined.0 + start.elapsed(),
+                joined.1 + books,
+                joined.2 + queries,
+            );
+        }
+    }
+
+    report("consultas por livro", per_book.0, per_book.1, per_book.2);
+    report("consulta única", joined.0, joined.1, joined.2);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), sqlx::Error> {
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL não definido");
+    let options = MySqlConnectOptions::from_str(&url)?;
+
+    let server = MySqlPoolOptions::new()
+        .max_connections(1)
+        .connect_with(options.clone())
+        .await?;
+
+    // a run that was interrupted leaves its database behind
+    sqlx::query(&format!("DROP DATABASE IF EXISTS `{}`", FIXTURE_DATABASE))
+        .execute(&server)
+        .await?;
+    sqlx::query(&format!("CREATE DATABASE `{}`", FIXTURE_DATABASE))
+        .execute(&server)
+        .await?;
+
+    let pool = MySqlPoolOptions::new()
+        .max_connections(1)
+        .connect_with(options.database(FIXTURE_DATABASE))
+        .await?;
+
+    let result = match seed(&pool).await {
+        Ok(()) => benchmark(&pool).await,
+        Err(e) => Err(e),
+    };
+
+    pool.close().await;
+    sqlx::query(&format!("DROP DATABASE IF EXISTS `{}`", FIXTURE_DATABASE))
+        .execute(&server)
+        .await?;
+
+    result
+}
@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{mysql::MySqlRow, MySqlConnection};
+
+use crate::{
+    db_structs::RegistoAuditoria, errors::CommandError, jwt::get_from_jwt,
+    permissions::verify_permission, requests::page_bounds, Database,
+};
+
+pub const CREATE: &str = "criar";
+pub const UPDATE: &str = "atualizar";
+pub const DELETE: &str = "apagar";
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    pub id: i32,
+    pub id_bibliotecario: Option<i32>,
+    pub bibliotecario: Option<String>,
+    pub acao: String,
+    pub entidade: String,
+    pub id_entidade: String,
+    pub antes: Option<Value>,
+    pub depois: Option<Value>,
+    pub data: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct AuditLogFilter {
+    pub entity: Option<String>,
+    pub entity_id: Option<String>,
+    pub action: Option<String>,
+    pub librarian_id: Option<i32>,
+    // dates as YYYY-MM-DD, both ends inclusive
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditLogEntry>,
+    pub total: i64,
+}
+
+// reads a row as json so it can be stored as the before or after state of a change,
+// locked inside the transaction of the change so no other write lands in between
+pub async fn snapshot<T>(
+    query: &str,
+    id: i32,
+    conn: &mut MySqlConnection,
+) -> Result<Option<Value>, String>
+where
+    T: for<'r> sqlx::FromRow<'r, MySqlRow> + Serialize + Send + Unpin,
+{
+    let row = sqlx::query_as::<_, T>(&format!("{} FOR UPDATE", query))
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar registo: {}", e);
+            format!("Falha ao consultar registo: {}", e)
+        })?;
+
+    row.map(|row| serde_json::to_value(row))
+        .transpose()
+        .map_err(|e| {
+            tracing::error!("Falha ao serializar registo: {}", e);
+            format!("Falha ao serializar registo: {}", e)
+        })
+}
+
+// token is None only for changes made before any librarian exists, like the first access.
+// written on the transaction of the change, so the entry and the change commit or roll back together
+pub async fn record_audit(
+    token: Option<&str>,
+    action: &str,
+    entity: &str,
+    entity_id: impl ToString,
+    before: Option<Value>,
+    after: Option<Value>,
+    conn: &mut MySqlConnection,
+) -> Result<(), String> {
+    let librarian_id = match token {
+        Some(token) => Some(
+            get_from_jwt(token)?
+                .get("id")
+                .and_then(|id| id.parse::<i32>().ok())
+                .ok_or("token inválido")?,
+        ),
+        None => None,
+    };
+
+    sqlx::query(
+        "INSERT INTO auditoria (id_bibliotecario, acao, entidade, id_entidade, antes, depois, data) VALUES (?, ?, ?, ?, ?, ?, NOW())",
+    )
+    .bind(librarian_id)
+    .bind(action)
+    .bind(entity)
+    .bind(entity_id.to_string())
+    .bind(before.map(|value| value.to_string()))
+    .bind(after.map(|value| value.to_string()))
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao registar auditoria: {}", e);
+        format!("Falha ao registar auditoria: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_audit_log(
+    token: String,
+    limit: i32,
+    offset: i32,
+    filter: Option<AuditLogFilter>,
+    state: tauri::State<'_, Database>,
+) -> Result<AuditLogPage, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "ver_auditoria", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let (limit, offset) = page_bounds(limit, offset)?;
+
+    let filter = filter.unwrap_or_default();
+
+    let filters = "(? IS NULL OR auditoria.entidade = ?)
+        AND (? IS NULL OR auditoria.id_entidade = ?)
+        AND (? IS NULL OR auditoria.acao = ?)
+        AND (? IS NULL OR auditoria.id_bibliotecario = ?)
+        AND (? IS NULL OR auditoria.data >= ?)
+        AND (? IS NULL OR auditoria.data < DATE_ADD(?, INTERVAL 1 DAY))";
+
+    let total: i64 =
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM auditoria WHERE {}", filters))
+            .bind(&filter.entity)
+            .bind(&filter.entity)
+            .bind(&filter.entity_id)
+            .bind(&filter.entity_id)
+            .bind(&filter.action)
+            .bind(&filter.action)
+            .bind(filter.librarian_id)
+            .bind(filter.librarian_id)
+            .bind(&filter.from)
+            .bind(&filter.from)
+            .bind(&filter.to)
+            .bind(&filter.to)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar auditoria: {}", e);
+                format!("Falha ao consultar auditoria: {}", e)
+            })?;
+
+    let rows = sqlx::query_as::<_, RegistoAuditoria>(&format!(
+        "SELECT auditoria.*, bibliotecarios.nome AS bibliotecario FROM auditoria
+        LEFT JOIN bibliotecarios ON bibliotecarios.id = auditoria.id_bibliotecario
+        WHERE {}
+        ORDER BY auditoria.data DESC, auditoria.id DESC
+        LIMIT ? OFFSET ?",
+        filters
+    ))
+    .bind(&filter.entity)
+    .bind(&filter.entity)
+    .bind(&filter.entity_id)
+    .bind(&filter.entity_id)
+    .bind(&filter.action)
+    .bind(&filter.action)
+    .bind(filter.librarian_id)
+    .bind(filter.librarian_id)
+    .bind(&filter.from)
+    .bind(&filter.from)
+    .bind(&filter.to)
+    .bind(&filter.to)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar auditoria: {}", e);
+        format!("Falha ao consultar auditoria: {}", e)
+    })?;
+
+    let entries = rows
+        .into_iter()
+        .map(|row| AuditLogEntry {
+            id: row.id,
+            id_bibliotecario: row.id_bibliotecario,
+            bibliotecario: row.bibliotecario,
+            acao: row.acao,
+            entidade: row.entidade,
+            id_entidade: row.id_entidade,
+            antes: row
+                .antes
+                .and_then(|value| serde_json::from_str(&value).ok()),
+            depois: row
+                .depois
+                .and_then(|value| serde_json::from_str(&value).ok()),
+            data: row.data,
+        })
+        .collect();
+
+    Ok(AuditLogPage { entries, total })
+}
@@ -1,22 +1,20 @@
-use tokio::sync::Mutex;
-
 use crate::{
-    db_structs::{Autor, Livro, LivroAsResponse},
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
+    book_rows::books_query,
+    books::fetch_books,
+    db_structs::{Autor, LivroAsResponse},
+    errors::CommandError,
     jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
     Database,
 };
 
 #[tauri::command]
 pub async fn get_author_by_id(
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Autor, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Autor, CommandError> {
+    let pool = &state.pool().await?;
 
     let author = sqlx::query_as::<_, Autor>("SELECT * FROM autores WHERE id = ?")
         .bind(id)
@@ -28,7 +26,7 @@ pub async fn get_author_by_id(
         })?;
 
     if author.is_empty() {
-        return Err("Autor não encontrado".to_string());
+        return Err("Autor não encontrado".to_string().into());
     }
 
     Ok(author[0].clone())
@@ -37,14 +35,9 @@ pub async fn get_author_by_id(
 #[tauri::command]
 pub async fn get_authors_by_name(
     name: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Autor>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Autor>, CommandError> {
+    let pool = &state.pool().await?;
 
     let authors =
         sqlx::query_as::<_, Autor>("SELECT * FROM autores WHERE LOWER(nome) LIKE LOWER(?)")
@@ -62,95 +55,16 @@ pub async fn get_authors_by_name(
 #[tauri::command]
 pub async fn get_books_by_author_id(
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<LivroAsResponse>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
-
-    let books = sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE id_autor = ?")
-        .bind(id)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar: {}", e);
-            format!("Falha ao consultar: {}", e)
-        })?;
-
-    let mut respose_books: Vec<LivroAsResponse> = Vec::new();
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<LivroAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
 
-    for book in books {
-        let autor = sqlx::query_scalar::<_, String>("SELECT nome FROM autores WHERE id = ?")
-            .bind(book.id_autor)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar autor: {}", e);
-                format!("Falha ao consultar autor: {}", e)
-            })?;
-
-        let categoria = sqlx::query_scalar::<_, String>(
-            "SELECT nome FROM categorias WHERE id = (SELECT id_categoria FROM sub_categorias WHERE id = ?)",
-        )
-        .bind(book.id_sub_categoria)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar categoria: {}", e);
-            format!("Falha ao consultar categoria: {}", e)
-        })?;
+    // an author credited twice on the same book, as author and illustrator for example, lists it once
+    let query = books_query(
+        "SELECT * FROM livros WHERE id IN (SELECT id_livro FROM livros_autores WHERE id_autor = ?)",
+    );
 
-        let sub_categoria =
-            sqlx::query_scalar::<_, String>("SELECT nome FROM sub_categorias WHERE id = ?")
-                .bind(book.id_sub_categoria)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Falha ao consultar sub-categoria: {}", e);
-                    format!("Falha ao consultar sub-categoria: {}", e)
-                })?;
-
-        let publisher = sqlx::query_scalar::<_, String>("SELECT nome FROM editoras WHERE id = ?")
-            .bind(book.id_editora)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar editora: {}", e);
-                format!("Falha ao consultar editora: {}", e)
-            })?;
-
-        let is_requested: i32 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL",
-    )
-    .bind(book.id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Falha ao consultar requisição: {}", e);
-        format!("Falha ao consultar requisição: {}", e)
-    })?;
-
-        respose_books.push(LivroAsResponse {
-            id: book.id,
-            autor: Some(autor),
-            categoria: Some(categoria),
-            sub_categoria: Some(sub_categoria),
-            ano_edicao: book.ano_edicao,
-            img_url: book.img_url,
-            resumo: book.resumo,
-            autor_id: book.id_autor,
-            editora: publisher,
-            idioma: book.idioma,
-            nome: book.nome,
-            n_paginas: book.n_paginas,
-            requisitado: is_requested > 0,
-        });
-    }
-
-    Ok(respose_books)
+    Ok(fetch_books(sqlx::query_as(&query).bind(id), pool).await?)
 }
 
 #[tauri::command]
@@ -159,24 +73,17 @@ pub async fn get_authors(
     limit: i32,
     offset: i32,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Autor>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Autor>, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let authors;
-
-    if search.is_none() {
-        authors = sqlx::query_as::<_, Autor>("SELECT * FROM autores LIMIT ? OFFSET ?")
+    let authors = match search {
+        None => sqlx::query_as::<_, Autor>("SELECT * FROM autores LIMIT ? OFFSET ?")
             .bind(limit)
             .bind(offset)
             .fetch_all(pool)
@@ -184,12 +91,11 @@ pub async fn get_authors(
             .map_err(|e| {
                 tracing::error!("Falha ao consultar autores: {}", e);
                 format!("Falha ao consultar autores: {}", e)
-            })?;
-    } else {
-        authors = sqlx::query_as::<_, Autor>(
+            })?,
+        Some(search) => sqlx::query_as::<_, Autor>(
             "SELECT * FROM autores WHERE LOWER(nome) LIKE LOWER(?) LIMIT ? OFFSET ?",
         )
-        .bind(format!("%{}%", search.unwrap().to_lowercase()))
+        .bind(format!("%{}%", search.to_lowercase()))
         .bind(limit)
         .bind(offset)
         .fetch_all(pool)
@@ -197,8 +103,8 @@ pub async fn get_authors(
         .map_err(|e| {
             tracing::error!("Falha ao consultar autores: {}", e);
             format!("Falha ao consultar autores: {}", e)
-        })?;
-    }
+        })?,
+    };
 
     Ok(authors)
 }
@@ -207,40 +113,34 @@ pub async fn get_authors(
 pub async fn get_authors_count(
     token: String,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<i32, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<i32, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let count: i32;
-
-    if search.is_none() {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM autores")
+    let count: i32 = match search {
+        None => sqlx::query_scalar("SELECT COUNT(*) FROM autores")
             .fetch_one(pool)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar autores: {}", e);
                 format!("Falha ao consultar autores: {}", e)
-            })?;
-    } else {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM autores WHERE LOWER(nome) LIKE LOWER(?)")
-            .bind(format!("%{}%", search.unwrap().to_lowercase()))
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar autores: {}", e);
-                format!("Falha ao consultar autores: {}", e)
-            })?;
-    }
+            })?,
+        Some(search) => {
+            sqlx::query_scalar("SELECT COUNT(*) FROM autores WHERE LOWER(nome) LIKE LOWER(?)")
+                .bind(format!("%{}%", search.to_lowercase()))
+                .fetch_one(pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Falha ao consultar autores: {}", e);
+                    format!("Falha ao consultar autores: {}", e)
+                })?
+        }
+    };
 
     Ok(count)
 }
@@ -252,34 +152,46 @@ pub async fn create_author(
     nationality: String,
     birth_date: Option<String>,
     death_date: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "criar_autor", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    sqlx::query(
+    let result = sqlx::query(
         "INSERT INTO autores (nome, nacionalidade, data_nasc, data_morte) VALUES (?, ?, ?, ?)",
     )
     .bind(name)
     .bind(nationality)
     .bind(birth_date)
     .bind(death_date)
-    .execute(pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         tracing::error!("Falha ao criar autor: {}", e);
         format!("Falha ao criar autor: {}", e)
     })?;
 
+    let id = result.last_insert_id() as i32;
+    let after = snapshot::<Autor>("SELECT * FROM autores WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "autores", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -291,33 +203,50 @@ pub async fn update_author(
     nationality: String,
     birth_date: Option<String>,
     death_date: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "atualizar_autor", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before = snapshot::<Autor>("SELECT * FROM autores WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Autor não encontrado".to_string().into());
+    }
+
     sqlx::query("UPDATE autores SET nome = ?, nacionalidade = ?, data_nasc = ?, data_morte = ? WHERE id = ?")
         .bind(name)
         .bind(nationality)
         .bind(birth_date)
         .bind(death_date)
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao atualizar autor: {}", e);
             format!("Falha ao atualizar autor: {}", e)
         })?;
 
+    let after = snapshot::<Autor>("SELECT * FROM autores WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), UPDATE, "autores", id, before, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -325,28 +254,43 @@ pub async fn update_author(
 pub async fn delete_author(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "apagar_autor", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before = snapshot::<Autor>("SELECT * FROM autores WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Autor não encontrado".to_string().into());
+    }
+
     sqlx::query("DELETE FROM autores WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao deletar autor: {}", e);
             format!("Falha ao deletar autor: {}", e)
         })?;
 
+    record_audit(Some(&token), DELETE, "autores", id, before, None, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
@@ -0,0 +1,183 @@
+const CODE128_START_B: u8 = 104;
+const CODE128_STOP: u8 = 106;
+
+// bar and space widths in modules of every symbol value, starting with a bar
+const CODE128_PATTERNS: [&[u8; 6]; 106] = [
+    b"212222", b"222122", b"222221", b"121223", b"121322", b"131222", b"122213", b"122312",
+    b"132212", b"221213", b"221312", b"231212", b"112232", b"122132", b"122231", b"113222",
+    b"123122", b"123221", b"223211", b"221132", b"221231", b"213212", b"223112", b"312131",
+    b"311222", b"321122", b"321221", b"312212", b"322112", b"322211", b"212123", b"212321",
+    b"232121", b"111323", b"131123", b"131321", b"112313", b"132113", b"132311", b"211313",
+    b"231113", b"231311", b"112133", b"112331", b"132131", b"113123", b"113321", b"133121",
+    b"313121", b"211331", b"231131", b"213113", b"213311", b"213131", b"311123", b"311321",
+    b"331121", b"312113", b"312311", b"332111", b"314111", b"221411", b"431111", b"111224",
+    b"111422", b"121124", b"121421", b"141122", b"141221", b"112214", b"112412", b"122114",
+    b"122411", b"142112", b"142211", b"241211", b"221114", b"413111", b"241112", b"134111",
+    b"111242", b"121142", b"121241", b"114212", b"124112", b"124211", b"411212", b"421112",
+    b"421211", b"212141", b"214121", b"412121", b"111143", b"111341", b"131141", b"114113",
+    b"114311", b"411113", b"411311", b"113141", b"114131", b"311141", b"411131", b"211412",
+    b"211214", b"211232",
+];
+const CODE128_STOP_PATTERN: &[u8; 7] = b"2331112";
+
+// symbol values of data encoded in Code 128 set B: start, data, check symbol and stop
+pub fn code128_symbols(data: &str) -> Result<Vec<u8>, String> {
+    if data.is_empty() {
+        return Err("O código de barras está vazio".to_string());
+    }
+
+    let mut symbols = vec![CODE128_START_B];
+
+    for c in data.chars() {
+        if !(' '..='~').contains(&c) {
+            return Err(format!(
+                "O carácter '{}' não pode ser codificado em Code 128",
+                c
+            ));
+        }
+
+        symbols.push(c as u8 - b' ');
+    }
+
+    // the start symbol and the first data symbol both weigh 1
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .map(|(position, value)| position.max(1) as u32 * *value as u32)
+        .sum::<u32>()
+        % 103;
+
+    symbols.push(checksum as u8);
+    symbols.push(CODE128_STOP);
+
+    Ok(symbols)
+}
+
+// bars (true) and spaces of the printed symbol, without the quiet zones around it
+pub fn code128_modules(data: &str) -> Result<Vec<bool>, String> {
+    let mut modules = Vec::new();
+
+    for symbol in code128_symbols(data)? {
+        let pattern: &[u8] = match symbol {
+            CODE128_STOP => CODE128_STOP_PATTERN,
+            symbol => CODE128_PATTERNS[symbol as usize],
+        };
+
+        for (position, width) in pattern.iter().enumerate() {
+            modules.extend(std::iter::repeat_n(
+                position % 2 == 0,
+                (width - b'0') as usize,
+            ));
+        }
+    }
+
+    Ok(modules)
+}
+
+pub fn ean13_check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .take(12)
+        .enumerate()
+        .map(|(position, digit)| *digit as u32 * if position % 2 == 0 { 1 } else { 3 })
+        .sum();
+
+    ((10 - sum % 10) % 10) as u8
+}
+
+pub fn is_valid_ean13(code: &str) -> bool {
+    if code.len() != 13 || !code.bytes().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+
+    let digits: Vec<u8> = code.bytes().map(|c| c - b'0').collect();
+
+    ean13_check_digit(&digits) == digits[12]
+}
+
+// scanners send the code followed by enter, so surrounding whitespace is dropped
+// 13 digit codes are read as EAN-13 and anything else must be encodable as Code 128.
+// scanners check and strip the Code 128 check character instead of sending it, so only the
+// character set can be validated here, while EAN-13 keeps its check digit in the data
+pub fn validate_barcode(code: &str) -> Result<String, String> {
+    let code = code.trim();
+
+    if code.len() == 13 && code.bytes().all(|c| c.is_ascii_digit()) {
+        if !is_valid_ean13(code) {
+            return Err(format!(
+                "O código EAN-13 {} tem um dígito de controlo inválido",
+                code
+            ));
+        }
+
+        return Ok(code.to_string());
+    }
+
+    code128_symbols(code)?;
+
+    Ok(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code128_symbols_end_with_check_and_stop() {
+        // start B 104, P 48, J 42, J 42, 1 17, 2 18, 3 19, C 35
+        // (104 + 48 + 2 * 42 + 3 * 42 + 4 * 17 + 5 * 18 + 6 * 19 + 7 * 35) % 103 = 55
+        assert_eq!(
+            code128_symbols("PJJ123C").unwrap(),
+            vec![104, 48, 42, 42, 17, 18, 19, 35, 55, 106]
+        );
+    }
+
+    #[test]
+    fn code128_modules_draw_every_symbol() {
+        // start B 211214, A 111323, check (104 + 33) % 103 = 34 131123, stop 2331112
+        let expected = "##.#..#....#.#...##...#...#.##...##...###.#.##";
+        let drawn: String = code128_modules("A")
+            .unwrap()
+            .into_iter()
+            .map(|bar| if bar { '#' } else { '.' })
+            .collect();
+
+        assert_eq!(drawn, expected);
+        // 11 modules per symbol, 13 for the stop
+        assert_eq!(code128_modules("PJJ123C").unwrap().len(), 9 * 11 + 13);
+    }
+
+    #[test]
+    fn code128_rejects_what_set_b_can_not_encode() {
+        assert!(code128_symbols("").is_err());
+        assert!(code128_symbols("ação").is_err());
+        assert!(code128_symbols("A\tB").is_err());
+    }
+
+    #[test]
+    fn ean13_check_digit_matches_known_codes() {
+        for code in ["4006381333931", "9780306406157", "5901234123457"] {
+            let digits: Vec<u8> = code.bytes().map(|c| c - b'0').collect();
+
+            assert_eq!(ean13_check_digit(&digits), digits[12], "{}", code);
+            assert!(is_valid_ean13(code));
+        }
+
+        assert!(!is_valid_ean13("4006381333932"));
+        assert!(!is_valid_ean13("400638133393"));
+    }
+
+    #[test]
+    fn barcodes_are_trimmed_and_validated() {
+        assert_eq!(
+            validate_barcode(" 4006381333931\n").unwrap(),
+            "4006381333931"
+        );
+        assert!(validate_barcode("4006381333932").is_err());
+        // other lengths of digits are Code 128 and have no check digit of their own
+        assert_eq!(validate_barcode("400638133393").unwrap(), "400638133393");
+        assert_eq!(validate_barcode("EX00000001").unwrap(), "EX00000001");
+        assert!(validate_barcode("   ").is_err());
+        assert!(validate_barcode("código").is_err());
+    }
+}
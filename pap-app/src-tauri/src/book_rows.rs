@@ -0,0 +1,134 @@
+// the book listings and the benchmark in examples share this module, so it only depends on db_structs
+use crate::db_structs::{AutorLivro, LivroAsResponse};
+
+// the page of books is picked by the inner query and every column of the response comes back in the same
+// round trip, a book credited to several authors spans one row per author in credit order
+const BOOKS_QUERY: &str = include_str!("books_query.sql");
+
+#[derive(sqlx::FromRow)]
+pub struct BookRow {
+    id: i32,
+    nome: String,
+    resumo: Option<String>,
+    n_paginas: i32,
+    idioma: String,
+    img_url: Option<String>,
+    ano_edicao: Option<String>,
+    isbn10: Option<String>,
+    isbn13: Option<String>,
+    editora: String,
+    categoria: Option<String>,
+    sub_categoria: Option<String>,
+    requisitado: bool,
+    exemplares: i64,
+    exemplares_disponiveis: i64,
+    id_autor: Option<i32>,
+    autor: Option<String>,
+    papel: Option<String>,
+}
+
+// `books` is any query over livros, the ones it selects are the ones in the response
+pub fn books_query(books: &str) -> String {
+    BOOKS_QUERY.replace("{books}", books)
+}
+
+// rows come ordered by book, so the rows of a book are next to each other and each one adds a credit
+pub fn group_books(rows: Vec<BookRow>) -> Vec<LivroAsResponse> {
+    let mut books: Vec<LivroAsResponse> = Vec::new();
+
+    for row in rows {
+        let author = match (row.id_autor, row.autor, row.papel) {
+            (Some(id), Some(nome), Some(papel)) => Some(AutorLivro { id, nome, papel }),
+            _ => None,
+        };
+
+        match books.last_mut() {
+            Some(book) if book.id == row.id => book.autores.extend(author),
+            _ => books.push(LivroAsResponse {
+                id: row.id,
+                nome: row.nome,
+                resumo: row.resumo,
+                n_paginas: row.n_paginas,
+                idioma: row.idioma,
+                img_url: row.img_url,
+                ano_edicao: row.ano_edicao,
+                isbn10: row.isbn10,
+                isbn13: row.isbn13,
+                autores: author.into_iter().collect(),
+                editora: row.editora,
+                categoria: row.categoria,
+                sub_categoria: row.sub_categoria,
+                requisitado: row.requisitado,
+                exemplares: row.exemplares,
+                exemplares_disponiveis: row.exemplares_disponiveis,
+            }),
+        }
+    }
+
+    books
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: i32, author: Option<(i32, &str, &str)>) -> BookRow {
+        BookRow {
+            id,
+            nome: format!("Livro {}", id),
+            resumo: None,
+            n_paginas: 100,
+            idioma: "Português".to_string(),
+            img_url: None,
+            ano_edicao: None,
+            isbn10: None,
+            isbn13: None,
+            editora: "Porto Editora".to_string(),
+            categoria: None,
+            sub_categoria: None,
+            requisitado: false,
+            exemplares: 1,
+            exemplares_disponiveis: 1,
+            id_autor: author.map(|(id, _, _)| id),
+            autor: author.map(|(_, nome, _)| nome.to_string()),
+            papel: author.map(|(_, _, papel)| papel.to_string()),
+        }
+    }
+
+    fn credits(book: &LivroAsResponse) -> Vec<(i32, &str)> {
+        book.autores
+            .iter()
+            .map(|author| (author.id, author.papel.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn rows_of_a_book_become_its_credits_in_order() {
+        let books = group_books(vec![
+            row(1, Some((7, "Autor 7", "autor"))),
+            row(1, Some((3, "Autor 3", "tradutor"))),
+            // the same person credited twice with different roles
+            row(1, Some((7, "Autor 7", "ilustrador"))),
+            // the left join gives a book without credits a row of nulls
+            row(2, None),
+            row(3, Some((3, "Autor 3", "autor"))),
+        ]);
+
+        assert_eq!(
+            books.iter().map(|book| book.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            credits(&books[0]),
+            vec![(7, "autor"), (3, "tradutor"), (7, "ilustrador")]
+        );
+        assert!(books[1].autores.is_empty());
+        assert_eq!(credits(&books[2]), vec![(3, "autor")]);
+        assert_eq!(books[2].nome, "Livro 3");
+    }
+
+    #[test]
+    fn no_rows_is_no_books() {
+        assert!(group_books(Vec::new()).is_empty());
+    }
+}
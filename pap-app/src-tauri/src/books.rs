@@ -1,274 +1,286 @@
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{mysql::MySqlArguments, query::QueryAs, MySql, MySqlConnection, Pool};
 
 use crate::{
-    db_structs::{Livro, LivroAsResponse},
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
+    book_rows::{books_query, group_books, BookRow},
+    db_structs::{AutorLivro, Livro, LivroAsResponse},
+    errors::CommandError,
+    isbn::{parse_isbn, parse_optional_isbn, Isbn},
     jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
     Database,
 };
 
-#[tauri::command]
-pub async fn get_books(
-    token: String,
-    limit: i32,
-    offset: i32,
-    search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<LivroAsResponse>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+pub const AUTHOR_ROLES: [&str; 4] = ["autor", "tradutor", "ilustrador", "editor"];
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
-    })?;
+// authors are credited in the order they are sent
+#[derive(Deserialize)]
+pub struct BookAuthor {
+    pub author_id: i32,
+    pub role: String,
+}
 
-    let books;
+// a book whose ISBN is already in the catalogue is not inserted again, the existing record comes back instead
+#[derive(Serialize)]
+pub struct CreatedBook {
+    pub livro: LivroAsResponse,
+    pub duplicado: bool,
+}
 
-    if search.is_none() {
-        books = sqlx::query_as::<_, Livro>("SELECT * FROM livros LIMIT ? OFFSET ?")
-            .bind(limit)
-            .bind(offset)
-            .fetch_all(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar livros: {}", e);
-                format!("Falha ao consultar livros: {}", e)
-            })?;
-    } else {
-        books = sqlx::query_as::<_, Livro>(
-            "SELECT * FROM livros WHERE LOWER(nome) LIKE LOWER(?) LIMIT ? OFFSET ?",
-        )
-        .bind(format!("%{}%", search.unwrap().to_lowercase()))
-        .bind(limit)
-        .bind(offset)
-        .fetch_all(pool)
+async fn find_book_by_isbn(isbn: &Isbn, pool: &Pool<MySql>) -> Result<Option<Livro>, String> {
+    sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE isbn13 = ?")
+        .bind(&isbn.isbn13)
+        .fetch_optional(pool)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao consultar livros: {}", e);
-            format!("Falha ao consultar livros: {}", e)
-        })?;
+            tracing::error!("Falha ao consultar livro: {}", e);
+            format!("Falha ao consultar livro: {}", e)
+        })
+}
+
+fn validate_book_authors(authors: &[BookAuthor]) -> Result<(), String> {
+    if authors.is_empty() {
+        return Err("O livro tem de ter pelo menos um autor".to_string());
     }
 
-    let mut books_as_response = Vec::new();
+    for (i, author) in authors.iter().enumerate() {
+        if !AUTHOR_ROLES.contains(&author.role.as_str()) {
+            return Err(format!("Papel de autor inválido: {}", author.role));
+        }
+
+        if authors[..i]
+            .iter()
+            .any(|other| other.author_id == author.author_id && other.role == author.role)
+        {
+            return Err("O mesmo autor está repetido com o mesmo papel".to_string());
+        }
+    }
 
-    for book in books {
-        let autor = sqlx::query_scalar::<_, String>("SELECT nome FROM autores WHERE id = ?")
-            .bind(book.id_autor)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar autor: {}", e);
-                format!("Falha ao consultar autor: {}", e)
-            })?;
+    Ok(())
+}
 
-        let categoria = sqlx::query_scalar::<_, String>(
-            "SELECT nome FROM categorias WHERE id = (SELECT id_categoria FROM sub_categorias WHERE id = ?)",
+// replaces the credits of a book, runs inside the transaction that saves the book
+async fn set_book_authors(
+    book_id: i32,
+    authors: &[BookAuthor],
+    tx: &mut MySqlConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM livros_autores WHERE id_livro = ?")
+        .bind(book_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for (order, author) in authors.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO livros_autores (id_livro, id_autor, papel, ordem) VALUES (?, ?, ?, ?)",
         )
-        .bind(book.id_sub_categoria)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar categoria: {}", e);
-            format!("Falha ao consultar categoria: {}", e)
-        })?;
+        .bind(book_id)
+        .bind(author.author_id)
+        .bind(&author.role)
+        .bind(order as i32)
+        .execute(&mut *tx)
+        .await?;
+    }
 
-        let sub_categoria =
-            sqlx::query_scalar::<_, String>("SELECT nome FROM sub_categorias WHERE id = ?")
-                .bind(book.id_sub_categoria)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Falha ao consultar sub-categoria: {}", e);
-                    format!("Falha ao consultar sub-categoria: {}", e)
-                })?;
+    Ok(())
+}
 
-        let publisher = sqlx::query_scalar::<_, String>("SELECT nome FROM editoras WHERE id = ?")
-            .bind(book.id_editora)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar editora: {}", e);
-                format!("Falha ao consultar editora: {}", e)
-            })?;
+pub async fn get_book_authors(
+    book_id: i32,
+    conn: &mut MySqlConnection,
+) -> Result<Vec<AutorLivro>, String> {
+    sqlx::query_as::<_, AutorLivro>(
+        "SELECT autores.id, autores.nome, livros_autores.papel FROM livros_autores
+        INNER JOIN autores ON autores.id = livros_autores.id_autor
+        WHERE livros_autores.id_livro = ?
+        ORDER BY livros_autores.ordem",
+    )
+    .bind(book_id)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar autores: {}", e);
+        format!("Falha ao consultar autores: {}", e)
+    })
+}
 
-        let is_requested: i32 =
-            sqlx::query_scalar("SELECT COUNT(*) FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL")
-                .bind(book.id)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Falha ao consultar requisição: {}", e);
-                    format!("Falha ao consultar requisição: {}", e)
-                })?;
-
-        books_as_response.push(LivroAsResponse {
-            id: book.id,
-            nome: book.nome,
-            resumo: book.resumo,
-            n_paginas: book.n_paginas,
-            idioma: book.idioma,
-            img_url: book.img_url,
-            ano_edicao: book.ano_edicao,
-            autor: Some(autor),
-            autor_id: book.id_autor,
-            editora: publisher,
-            categoria: Some(categoria),
-            sub_categoria: Some(sub_categoria),
-            requisitado: is_requested > 0,
-        });
+// the audit log keeps the credits with the book since they are saved together
+async fn book_snapshot(id: i32, conn: &mut MySqlConnection) -> Result<Option<Value>, String> {
+    let mut book = snapshot::<Livro>("SELECT * FROM livros WHERE id = ?", id, &mut *conn).await?;
+
+    if let Some(Value::Object(fields)) = &mut book {
+        let authors = get_book_authors(id, conn).await?;
+
+        fields.insert(
+            "autores".to_string(),
+            serde_json::to_value(authors).map_err(|e| {
+                tracing::error!("Falha ao serializar registo: {}", e);
+                format!("Falha ao serializar registo: {}", e)
+            })?,
+        );
     }
 
-    Ok(books_as_response)
+    Ok(book)
+}
+
+pub async fn fetch_books(
+    statement: QueryAs<'_, MySql, BookRow, MySqlArguments>,
+    conn: &Pool<MySql>,
+) -> Result<Vec<LivroAsResponse>, String> {
+    let rows = statement.fetch_all(conn).await.map_err(|e| {
+        tracing::error!("Falha ao consultar livros: {}", e);
+        format!("Falha ao consultar livros: {}", e)
+    })?;
+
+    Ok(group_books(rows))
+}
+
+pub async fn get_book_as_response(id: i32, conn: &Pool<MySql>) -> Result<LivroAsResponse, String> {
+    let query = books_query("SELECT * FROM livros WHERE id = ?");
+
+    match fetch_books(sqlx::query_as(&query).bind(id), conn)
+        .await?
+        .pop()
+    {
+        Some(book) => Ok(book),
+        None => {
+            tracing::error!("Livro não encontrado");
+            Err("Livro não encontrado".to_string())
+        }
+    }
 }
 
 #[tauri::command]
-pub async fn get_book_by_id(
+pub async fn get_books(
     token: String,
-    id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<LivroAsResponse, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    limit: i32,
+    offset: i32,
+    search: Option<String>,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<LivroAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let book = sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE id = ?")
-        .bind(id)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar livro: {}", e);
-            format!("Falha ao consultar livro: {}", e)
-        })?;
+    let books = match search {
+        None => {
+            let query = books_query("SELECT * FROM livros ORDER BY id LIMIT ? OFFSET ?");
+
+            fetch_books(sqlx::query_as(&query).bind(limit).bind(offset), pool).await?
+        }
+        Some(search) => {
+            let query = books_query(
+                "SELECT * FROM livros WHERE LOWER(nome) LIKE LOWER(?) ORDER BY id LIMIT ? OFFSET ?",
+            );
+
+            fetch_books(
+                sqlx::query_as(&query)
+                    .bind(format!("%{}%", search.to_lowercase()))
+                    .bind(limit)
+                    .bind(offset),
+                pool,
+            )
+            .await?
+        }
+    };
+
+    Ok(books)
+}
 
-    if book.is_empty() {
-        tracing::error!("Livro não encontrado");
-        return Err("Livro não encontrado".to_string());
-    }
+#[tauri::command]
+pub async fn get_book_by_id(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<LivroAsResponse, CommandError> {
+    let pool = &state.pool().await?;
 
-    let book = book.first().unwrap().clone();
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
 
-    let autor = sqlx::query_scalar::<_, String>("SELECT nome FROM autores WHERE id = ?")
-        .bind(book.id_autor)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar autor: {}", e);
-            format!("Falha ao consultar autor: {}", e)
-        })?;
+    Ok(get_book_as_response(id, pool).await?)
+}
 
-    let categoria = sqlx::query_scalar::<_, String>(
-        "SELECT nome FROM categorias WHERE id = (SELECT id_categoria FROM sub_categorias WHERE id = ?)",
-    )
-    .bind(book.id_sub_categoria)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Falha ao consultar categoria: {}", e);
-        format!("Falha ao consultar categoria: {}", e)
+// accepts either form, with or without hyphens
+#[tauri::command]
+pub async fn get_book_by_isbn(
+    token: String,
+    isbn: String,
+    state: tauri::State<'_, Database>,
+) -> Result<LivroAsResponse, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
     })?;
 
-    let sub_categoria =
-        sqlx::query_scalar::<_, String>("SELECT nome FROM sub_categorias WHERE id = ?")
-            .bind(book.id_sub_categoria)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar sub-categoria: {}", e);
-                format!("Falha ao consultar sub-categoria: {}", e)
-            })?;
+    let isbn = parse_isbn(&isbn)?;
 
-    let publisher = sqlx::query_scalar::<_, String>("SELECT nome FROM editoras WHERE id = ?")
-        .bind(book.id_editora)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar editora: {}", e);
-            format!("Falha ao consultar editora: {}", e)
-        })?;
+    let query = books_query("SELECT * FROM livros WHERE isbn13 = ?");
 
-    let is_requested: i32 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL",
-    )
-    .bind(book.id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Falha ao consultar requisição: {}", e);
-        format!("Falha ao consultar requisição: {}", e)
-    })?;
+    match fetch_books(sqlx::query_as(&query).bind(&isbn.isbn13), pool)
+        .await?
+        .pop()
+    {
+        Some(book) => Ok(book),
+        None => {
+            tracing::error!("Livro não encontrado");
+            Err("Livro não encontrado".into())
+        }
+    }
+}
 
-    Ok(LivroAsResponse {
-        id: book.id,
-        nome: book.nome,
-        resumo: book.resumo,
-        n_paginas: book.n_paginas,
-        idioma: book.idioma,
-        img_url: book.img_url,
-        ano_edicao: book.ano_edicao,
-        autor: Some(autor),
-        autor_id: book.id_autor,
-        editora: publisher,
-        categoria: Some(categoria),
-        sub_categoria: Some(sub_categoria),
-        requisitado: is_requested > 0,
-    })
+#[tauri::command]
+pub fn validate_isbn(isbn: String) -> Result<Isbn, CommandError> {
+    Ok(parse_isbn(&isbn)?)
 }
 
 #[tauri::command]
 pub async fn get_books_count(
     token: String,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<i32, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<i32, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let count: i32;
-
-    if search.is_none() {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM livros")
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar livros: {}", e);
-                format!("Falha ao consultar livros: {}", e)
-            })?;
-    } else {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM livros WHERE LOWER(nome) LIKE LOWER(?)")
-            .bind(format!("%{}%", search.unwrap().to_lowercase()))
+    let count: i32 = match search {
+        None => sqlx::query_scalar("SELECT COUNT(*) FROM livros")
             .fetch_one(pool)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar livros: {}", e);
                 format!("Falha ao consultar livros: {}", e)
-            })?;
-    }
+            })?,
+        Some(search) => {
+            sqlx::query_scalar("SELECT COUNT(*) FROM livros WHERE LOWER(nome) LIKE LOWER(?)")
+                .bind(format!("%{}%", search.to_lowercase()))
+                .fetch_one(pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Falha ao consultar livros: {}", e);
+                    format!("Falha ao consultar livros: {}", e)
+                })?
+        }
+    };
 
     Ok(count)
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn create_book(
     token: String,
     name: String,
@@ -277,44 +289,101 @@ pub async fn create_book(
     language: String,
     img_url: Option<String>,
     ano_edicao: Option<String>,
-    author_id: String,
+    isbn: Option<String>,
+    authors: Vec<BookAuthor>,
     publisher_id: String,
     sub_category_id: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<CreatedBook, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "criar_livro", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    let pool = &db.pool;
+    validate_book_authors(&authors)?;
+    let isbn = parse_optional_isbn(isbn)?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    if let Some(isbn) = &isbn {
+        if let Some(book) = find_book_by_isbn(isbn, pool).await? {
+            tracing::warn!("Já existe um livro com o ISBN {}", isbn.isbn13);
+
+            return Ok(CreatedBook {
+                livro: get_book_as_response(book.id, pool).await?,
+                duplicado: true,
+            });
+        }
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    sqlx::query("INSERT INTO livros (nome, resumo, n_paginas, idioma, img_url, ano_edicao, id_autor, id_editora, id_sub_categoria) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")
+    let result = sqlx::query("INSERT INTO livros (nome, resumo, n_paginas, idioma, img_url, ano_edicao, isbn10, isbn13, id_editora, id_sub_categoria) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
         .bind(name)
         .bind(resume)
         .bind(n_pages)
         .bind(language)
         .bind(img_url)
         .bind(ano_edicao)
-        .bind(author_id)
+        .bind(isbn.as_ref().and_then(|isbn| isbn.isbn10.clone()))
+        .bind(isbn.as_ref().map(|isbn| isbn.isbn13.clone()))
         .bind(publisher_id)
         .bind(sub_category_id)
-        .execute(pool)
-        .await
-        .map_err(|e| {
+        .execute(&mut *tx)
+        .await;
+
+    let result = match (result, &isbn) {
+        (Ok(result), _) => result,
+        // another librarian added the same book since the check above
+        (Err(e), Some(isbn))
+            if e.as_database_error()
+                .is_some_and(|db_error| db_error.is_unique_violation()) =>
+        {
+            if let Some(book) = find_book_by_isbn(isbn, pool).await? {
+                return Ok(CreatedBook {
+                    livro: get_book_as_response(book.id, pool).await?,
+                    duplicado: true,
+                });
+            }
+
             tracing::error!("Falha ao criar livro: {}", e);
-            format!("Falha ao criar livro: {}", e)
-        })?;
+            return Err(format!("Falha ao criar livro: {}", e).into());
+        }
+        (Err(e), _) => {
+            tracing::error!("Falha ao criar livro: {}", e);
+            return Err(format!("Falha ao criar livro: {}", e).into());
+        }
+    };
 
-    Ok(())
+    let id = result.last_insert_id() as i32;
+
+    set_book_authors(id, &authors, &mut tx).await.map_err(|e| {
+        tracing::error!("Falha ao guardar autores: {}", e);
+        format!("Falha ao guardar autores: {}", e)
+    })?;
+
+    let after = book_snapshot(id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "livros", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(CreatedBook {
+        livro: get_book_as_response(id, pool).await?,
+        duplicado: false,
+    })
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_book(
     token: String,
     id: i32,
@@ -324,41 +393,73 @@ pub async fn update_book(
     language: String,
     img_url: Option<String>,
     ano_edicao: Option<String>,
-    author_id: String,
+    isbn: Option<String>,
+    authors: Vec<BookAuthor>,
     publisher_id: String,
     sub_category_id: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "atualizar_livro", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    validate_book_authors(&authors)?;
+    let isbn = parse_optional_isbn(isbn)?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    sqlx::query("UPDATE livros SET nome = ?, resumo = ?, n_paginas = ?, idioma = ?, img_url = ?, ano_edicao = ?, id_autor = ?, id_editora = ?, id_sub_categoria = ? WHERE id = ?")
+    let before = book_snapshot(id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Livro não encontrado".to_string().into());
+    }
+
+    sqlx::query("UPDATE livros SET nome = ?, resumo = ?, n_paginas = ?, idioma = ?, img_url = ?, ano_edicao = ?, isbn10 = ?, isbn13 = ?, id_editora = ?, id_sub_categoria = ? WHERE id = ?")
         .bind(name)
         .bind(resume)
         .bind(n_pages)
         .bind(language)
         .bind(img_url)
         .bind(ano_edicao)
-        .bind(author_id)
+        .bind(isbn.as_ref().and_then(|isbn| isbn.isbn10.clone()))
+        .bind(isbn.as_ref().map(|isbn| isbn.isbn13.clone()))
         .bind(publisher_id)
         .bind(sub_category_id)
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao atualizar livro: {}", e);
-            format!("Falha ao atualizar livro: {}", e)
+            match e.as_database_error() {
+                Some(db_error) if db_error.is_unique_violation() => {
+                    "Já existe outro livro com este ISBN".to_string()
+                }
+                _ => format!("Falha ao atualizar livro: {}", e),
+            }
         })?;
 
+    set_book_authors(id, &authors, &mut tx).await.map_err(|e| {
+        tracing::error!("Falha ao guardar autores: {}", e);
+        format!("Falha ao guardar autores: {}", e)
+    })?;
+
+    let after = book_snapshot(id, &mut tx).await?;
+
+    record_audit(Some(&token), UPDATE, "livros", id, before, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -366,28 +467,43 @@ pub async fn update_book(
 pub async fn delete_book(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "apagar_livro", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before = book_snapshot(id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Livro não encontrado".to_string().into());
+    }
+
     sqlx::query("DELETE FROM livros WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao deletar livro: {}", e);
             format!("Falha ao deletar livro: {}", e)
         })?;
 
+    record_audit(Some(&token), DELETE, "livros", id, before, None, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
@@ -0,0 +1,15 @@
+SELECT livros.id, livros.nome, livros.resumo, livros.n_paginas, livros.idioma, livros.img_url,
+    livros.ano_edicao, livros.isbn10, livros.isbn13,
+    editoras.nome AS editora, categorias.nome AS categoria, sub_categorias.nome AS sub_categoria,
+    EXISTS(SELECT * FROM requisicoes WHERE requisicoes.id_livro_requisitado = livros.id AND requisicoes.data_entrega IS NULL) AS requisitado,
+    (SELECT COUNT(*) FROM exemplares WHERE exemplares.id_livro = livros.id) AS exemplares,
+    (SELECT COUNT(*) FROM exemplares WHERE exemplares.id_livro = livros.id AND NOT EXISTS(
+        SELECT * FROM requisicoes WHERE requisicoes.id_exemplar = exemplares.id AND requisicoes.data_entrega IS NULL)) AS exemplares_disponiveis,
+    autores.id AS id_autor, autores.nome AS autor, livros_autores.papel
+    FROM ({books}) livros
+    INNER JOIN editoras ON editoras.id = livros.id_editora
+    LEFT JOIN sub_categorias ON sub_categorias.id = livros.id_sub_categoria
+    LEFT JOIN categorias ON categorias.id = sub_categorias.id_categoria
+    LEFT JOIN livros_autores ON livros_autores.id_livro = livros.id
+    LEFT JOIN autores ON autores.id = livros_autores.id_autor
+    ORDER BY livros.id, livros_autores.ordem
@@ -1,26 +1,22 @@
-use tokio::sync::Mutex;
-
 use crate::{
+    audit::{record_audit, snapshot, CREATE, DELETE},
     db_structs::{Categoria, SubCategoria},
+    errors::CommandError,
     jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
     Database,
 };
 
 #[tauri::command]
 pub async fn get_categories(
     token: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Categoria>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Categoria>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let sub_categories = sqlx::query_as::<_, Categoria>("SELECT * FROM categorias")
@@ -38,24 +34,33 @@ pub async fn get_categories(
 pub async fn delete_category(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "apagar_categoria", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before =
+        snapshot::<Categoria>("SELECT * FROM categorias WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Categoria não encontrada".to_string().into());
+    }
+
     let sub_categories =
         sqlx::query_as::<_, SubCategoria>("SELECT * FROM sub_categorias WHERE id_categoria = ?")
             .bind(id)
-            .fetch_all(pool)
+            .fetch_all(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar subcategorias: {}", e);
@@ -67,7 +72,7 @@ pub async fn delete_category(
             "SELECT EXISTS(SELECT * FROM livros WHERE id_sub_categoria = ?)",
         )
         .bind(sub_category.id)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar se sub-categoria é usada: {}", e);
@@ -76,20 +81,36 @@ pub async fn delete_category(
 
         if used {
             return Err(
-                "Esta categoria contem uma sub-categoria que tem livros associados, não é possível apagá-la".to_string(),
+                "Esta categoria contem uma sub-categoria que tem livros associados, não é possível apagá-la".to_string().into(),
             );
         }
     }
 
     sqlx::query("DELETE FROM categorias WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao deletar categoria: {}", e);
             format!("Falha ao deletar categoria: {}", e)
         })?;
 
+    record_audit(
+        Some(&token),
+        DELETE,
+        "categorias",
+        id,
+        before,
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -97,24 +118,26 @@ pub async fn delete_category(
 pub async fn create_category(
     token: String,
     name: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "criar_categoria", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
     let exists =
         sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT * FROM categorias WHERE nome = ?)")
             .bind(&name)
-            .fetch_one(pool)
+            .fetch_one(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar se categoria já existe: {}", e);
@@ -122,17 +145,27 @@ pub async fn create_category(
             })?;
 
     if exists {
-        return Err("Esta categoria já existe".to_string());
+        return Err("Esta categoria já existe".to_string().into());
     }
 
-    sqlx::query("INSERT INTO categorias (nome) VALUES (?)")
+    let result = sqlx::query("INSERT INTO categorias (nome) VALUES (?)")
         .bind(name)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao inserir categoria: {}", e);
             format!("Falha ao inserir categoria: {}", e)
         })?;
 
+    let id = result.last_insert_id() as i32;
+    let after = snapshot::<Categoria>("SELECT * FROM categorias WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "categorias", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
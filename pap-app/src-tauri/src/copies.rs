@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlConnection;
+
+use crate::{
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
+    barcodes::validate_barcode,
+    db_structs::Exemplar,
+    errors::CommandError,
+    jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
+    Database,
+};
+
+const DEFAULT_CONDITION: &str = "bom";
+// condition given to a copy that comes back damaged, it stays in the catalog but can not be lent
+pub const DAMAGED_CONDITION: &str = "danificado";
+const DEFAULT_CODE_PREFIX: &str = "EX";
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct ExemplarAsResponse {
+    pub id: i32,
+    pub id_livro: i32,
+    pub codigo: Option<String>,
+    pub localizacao: Option<String>,
+    pub estado: String,
+    pub data_aquisicao: Option<chrono::NaiveDate>,
+    pub requisitado: bool,
+}
+
+// (total, available) for a title counting only the copies that can be lent, a copy is available while it has no open loan
+pub async fn count_copies(book_id: i32, conn: &mut MySqlConnection) -> Result<(i64, i64), String> {
+    let counts: (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), CAST(COALESCE(SUM(NOT EXISTS(SELECT * FROM requisicoes WHERE requisicoes.id_exemplar = exemplares.id AND requisicoes.data_entrega IS NULL)), 0) AS SIGNED)
+        FROM exemplares WHERE id_livro = ? AND estado <> ?",
+    )
+    .bind(book_id)
+    .bind(DAMAGED_CONDITION)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar exemplares: {}", e);
+        format!("Falha ao consultar exemplares: {}", e)
+    })?;
+
+    Ok(counts)
+}
+
+// the accession number a copy gets when it is created without a code
+fn default_code(id: i32) -> String {
+    format!("{}{:08}", DEFAULT_CODE_PREFIX, id)
+}
+
+// accession numbers are derived from ids, so a typed one could collide with a later copy
+fn is_reserved_code(code: &str) -> bool {
+    code.strip_prefix(DEFAULT_CODE_PREFIX)
+        .is_some_and(|digits| digits.len() == 8 && digits.bytes().all(|c| c.is_ascii_digit()))
+}
+
+// runs inside the transaction of the insert so only the new copy is touched
+async fn assign_default_code(id: i32, conn: &mut MySqlConnection) -> Result<(), String> {
+    sqlx::query("UPDATE exemplares SET codigo = ? WHERE id = ? AND codigo IS NULL")
+        .bind(default_code(id))
+        .bind(id)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao atribuir código ao exemplar: {}", e);
+            format!("Falha ao atribuir código ao exemplar: {}", e)
+        })?;
+
+    Ok(())
+}
+
+pub async fn insert_copy(
+    book_id: i32,
+    code: Option<String>,
+    location: Option<String>,
+    condition: Option<String>,
+    acquisition_date: Option<chrono::NaiveDate>,
+    conn: &mut MySqlConnection,
+) -> Result<i32, String> {
+    let code = code
+        .filter(|code| !code.trim().is_empty())
+        .map(|code| validate_barcode(&code))
+        .transpose()?;
+
+    if code.as_deref().is_some_and(is_reserved_code) {
+        return Err(format!(
+            "Os códigos {}######## são atribuídos automaticamente",
+            DEFAULT_CODE_PREFIX
+        ));
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO exemplares (id_livro, codigo, localizacao, estado, data_aquisicao) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(book_id)
+    .bind(code)
+    .bind(location)
+    .bind(condition.unwrap_or_else(|| DEFAULT_CONDITION.to_string()))
+    .bind(acquisition_date)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao criar exemplar: {}", e);
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => {
+                "Já existe um exemplar com esse código".to_string()
+            }
+            _ => format!("Falha ao criar exemplar: {}", e),
+        }
+    })?;
+
+    let id = result.last_insert_id() as i32;
+
+    assign_default_code(id, conn).await?;
+
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn get_copies_by_book_id(
+    token: String,
+    book_id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<ExemplarAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let copies = sqlx::query_as::<_, ExemplarAsResponse>(
+        "SELECT exemplares.*, EXISTS(SELECT * FROM requisicoes WHERE requisicoes.id_exemplar = exemplares.id AND requisicoes.data_entrega IS NULL) AS requisitado
+        FROM exemplares WHERE id_livro = ? ORDER BY id",
+    )
+    .bind(book_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar exemplares: {}", e);
+        format!("Falha ao consultar exemplares: {}", e)
+    })?;
+
+    Ok(copies)
+}
+
+#[tauri::command]
+pub async fn create_copy(
+    token: String,
+    book_id: i32,
+    code: Option<String>,
+    location: Option<String>,
+    condition: Option<String>,
+    acquisition_date: Option<chrono::NaiveDate>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_livro", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let id = insert_copy(
+        book_id,
+        code,
+        location,
+        condition,
+        acquisition_date,
+        &mut tx,
+    )
+    .await?;
+    let after = snapshot::<Exemplar>("SELECT * FROM exemplares WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "exemplares", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn update_copy(
+    token: String,
+    id: i32,
+    code: String,
+    location: Option<String>,
+    condition: String,
+    acquisition_date: Option<chrono::NaiveDate>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_livro", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before = snapshot::<Exemplar>("SELECT * FROM exemplares WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Exemplar não encontrado".to_string().into());
+    }
+
+    let code = validate_barcode(&code)?;
+
+    // a copy may keep its own accession number but not take another one
+    if is_reserved_code(&code) && code != default_code(id) {
+        return Err(format!(
+            "Os códigos {}######## são atribuídos automaticamente",
+            DEFAULT_CODE_PREFIX
+        )
+        .into());
+    }
+
+    sqlx::query(
+        "UPDATE exemplares SET codigo = ?, localizacao = ?, estado = ?, data_aquisicao = ? WHERE id = ?",
+    )
+    .bind(code)
+    .bind(location)
+    .bind(condition)
+    .bind(acquisition_date)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao atualizar exemplar: {}", e);
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => {
+                "Já existe um exemplar com esse código".to_string()
+            }
+            _ => format!("Falha ao atualizar exemplar: {}", e),
+        }
+    })?;
+
+    let after = snapshot::<Exemplar>("SELECT * FROM exemplares WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "exemplares",
+        id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_copy(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_livro", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    // locking the copy first keeps a loan from starting between the check and the delete
+    let before = snapshot::<Exemplar>("SELECT * FROM exemplares WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Exemplar não encontrado".to_string().into());
+    }
+
+    let on_loan: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT * FROM requisicoes WHERE id_exemplar = ? AND data_entrega IS NULL)",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar requisição: {}", e);
+        format!("Falha ao consultar requisição: {}", e)
+    })?;
+
+    if on_loan {
+        return Err("Não é possível apagar um exemplar que está requisitado"
+            .to_string()
+            .into());
+    }
+
+    sqlx::query("DELETE FROM exemplares WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao apagar exemplar: {}", e);
+            format!("Falha ao apagar exemplar: {}", e)
+        })?;
+
+    record_audit(
+        Some(&token),
+        DELETE,
+        "exemplares",
+        id,
+        before,
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_codes_are_reserved() {
+        assert_eq!(default_code(42), "EX00000042");
+        assert!(is_reserved_code(&default_code(42)));
+        assert!(!is_reserved_code("EX0000042"));
+        assert!(!is_reserved_code("EX0000004A"));
+        assert!(!is_reserved_code("LE00000042"));
+    }
+}
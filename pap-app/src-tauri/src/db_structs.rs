@@ -15,6 +15,15 @@ pub struct Bibliotecario {
     pub nome: String,
     pub password: String,
     pub cargo: String,
+    pub alterar_password: bool,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ChaveJwt {
+    pub id: String,
+    pub segredo: String,
+    pub ativa: bool,
+    pub data_expiracao: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Serialize, Deserialize, sqlx::FromRow)]
@@ -33,6 +42,16 @@ pub struct Editora {
     pub email: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct Exemplar {
+    pub id: i32,
+    pub id_livro: i32,
+    pub codigo: Option<String>,
+    pub localizacao: Option<String>,
+    pub estado: String,
+    pub data_aquisicao: Option<chrono::NaiveDate>,
+}
+
 #[derive(Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Leitor {
     pub id: i32,
@@ -40,6 +59,17 @@ pub struct Leitor {
     pub morada: String,
     pub telefone: String,
     pub email: String,
+    pub id_tipo: i32,
+    pub numero_cartao: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct TipoLeitor {
+    pub id: i32,
+    pub nome: String,
+    pub max_requisicoes: Option<i32>,
+    pub dias_emprestimo: Option<i32>,
+    pub max_renovacoes: Option<i32>,
 }
 
 #[derive(Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -51,18 +81,79 @@ pub struct Livro {
     pub idioma: String,
     pub img_url: Option<String>,
     pub ano_edicao: Option<String>,
-    pub id_autor: Option<i32>,
     pub id_editora: i32,
     pub id_sub_categoria: Option<i32>,
+    pub isbn10: Option<String>,
+    pub isbn13: Option<String>,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Requisicao {
     pub id: i32,
     pub id_leitor: i32,
     pub id_livro_requisitado: i32,
+    pub id_exemplar: Option<i32>,
     pub data_requisicao: chrono::DateTime<chrono::Utc>,
+    pub data_limite: Option<chrono::DateTime<chrono::Utc>>,
+    pub renovacoes: i32,
     pub data_entrega: Option<chrono::DateTime<chrono::Utc>>,
+    pub notas_devolucao: Option<String>,
+    pub danificado: bool,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct Reserva {
+    pub id: i32,
+    pub id_livro: i32,
+    pub id_leitor: i32,
+    pub estado: String,
+    pub data_reserva: chrono::DateTime<chrono::Utc>,
+    pub data_disponivel: Option<chrono::DateTime<chrono::Utc>>,
+    pub data_expiracao: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct MovimentoLeitor {
+    pub id: i32,
+    pub id_leitor: i32,
+    pub id_requisicao: Option<i32>,
+    pub id_bibliotecario: Option<i32>,
+    pub tipo: String,
+    pub valor: i32,
+    pub descricao: Option<String>,
+    pub data: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct HistoricoRequisicao {
+    pub id: i32,
+    pub id_requisicao: i32,
+    pub id_bibliotecario: Option<i32>,
+    pub bibliotecario: Option<String>,
+    pub evento: String,
+    pub data_limite_anterior: Option<chrono::DateTime<chrono::Utc>>,
+    pub data_limite: Option<chrono::DateTime<chrono::Utc>>,
+    pub data: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct Sessao {
+    pub id: String,
+    pub id_bibliotecario: i32,
+    pub refresh_token: String,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct RegistoAuditoria {
+    pub id: i32,
+    pub id_bibliotecario: Option<i32>,
+    pub bibliotecario: Option<String>,
+    pub acao: String,
+    pub entidade: String,
+    pub id_entidade: String,
+    pub antes: Option<String>,
+    pub depois: Option<String>,
+    pub data: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Serialize, Deserialize, sqlx::FromRow)]
@@ -85,6 +176,14 @@ pub struct Cargo {
     pub permissao: i32,
 }
 
+// an author as credited on a book, in the order the book lists them
+#[derive(Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AutorLivro {
+    pub id: i32,
+    pub nome: String,
+    pub papel: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct LivroAsResponse {
     pub id: i32,
@@ -94,10 +193,13 @@ pub struct LivroAsResponse {
     pub idioma: String,
     pub img_url: Option<String>,
     pub ano_edicao: Option<String>,
-    pub autor: Option<String>,
-    pub autor_id: Option<i32>,
+    pub isbn10: Option<String>,
+    pub isbn13: Option<String>,
+    pub autores: Vec<AutorLivro>,
     pub editora: String,
     pub categoria: Option<String>,
     pub sub_categoria: Option<String>,
     pub requisitado: bool,
+    pub exemplares: i64,
+    pub exemplares_disponiveis: i64,
 }
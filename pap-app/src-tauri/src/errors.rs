@@ -0,0 +1,136 @@
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{permissions::AuthError, requests::LoanError};
+
+// commands return this to the frontend, whose kind tells errors apart without matching their message
+#[derive(Debug)]
+pub enum CommandError {
+    Auth(AuthError),
+    Loan(LoanError),
+    Failed(String),
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Auth(e) => write!(f, "{}", e),
+            CommandError::Loan(e) => write!(f, "{}", e),
+            CommandError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<AuthError> for CommandError {
+    fn from(e: AuthError) -> CommandError {
+        CommandError::Auth(e)
+    }
+}
+
+impl From<LoanError> for CommandError {
+    fn from(e: LoanError) -> CommandError {
+        CommandError::Loan(e)
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> CommandError {
+        CommandError::Failed(message)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> CommandError {
+        CommandError::Failed(message.to_string())
+    }
+}
+
+// the typed errors keep their own kind and detail and every error carries the message to show
+#[derive(Serialize)]
+struct WithMessage<'a, T: Serialize> {
+    #[serde(flatten)]
+    error: &'a T,
+    message: String,
+}
+
+impl Serialize for CommandError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            CommandError::Auth(e) => WithMessage {
+                error: e,
+                message: e.to_string(),
+            }
+            .serialize(serializer),
+            CommandError::Loan(e) => WithMessage {
+                error: e,
+                message: e.to_string(),
+            }
+            .serialize(serializer),
+            CommandError::Failed(message) => {
+                let mut state = serializer.serialize_struct("CommandError", 2)?;
+                state.serialize_field("kind", "Failed")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_errors_keep_their_kind_and_detail() {
+        let error = CommandError::from(AuthError::Forbidden("criar_livro".to_string()));
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "kind": "Forbidden",
+                "detail": "criar_livro",
+                "message": "Sem permissão para realizar esta ação (criar_livro)",
+            })
+        );
+    }
+
+    #[test]
+    fn unit_variants_have_no_detail() {
+        let error = CommandError::from(AuthError::ExpiredToken);
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "kind": "ExpiredToken",
+                "message": "Falha ao verificar token: token expirado",
+            })
+        );
+    }
+
+    #[test]
+    fn loan_errors_name_the_borrower() {
+        let error = CommandError::from(LoanError::AlreadyOnLoan {
+            copy: "EX00000001".to_string(),
+            reader_id: 7,
+            reader: "Ana".to_string(),
+        });
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "kind": "AlreadyOnLoan",
+                "detail": { "copy": "EX00000001", "reader_id": 7, "reader": "Ana" },
+                "message": "O exemplar EX00000001 já está requisitado por Ana (leitor 7)",
+            })
+        );
+    }
+
+    #[test]
+    fn other_errors_are_failures() {
+        let error = CommandError::from("Livro não encontrado");
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({ "kind": "Failed", "message": "Livro não encontrado" })
+        );
+    }
+}
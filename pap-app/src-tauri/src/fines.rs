@@ -0,0 +1,397 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::{
+    audit::{record_audit, snapshot, CREATE},
+    db_structs::MovimentoLeitor,
+    errors::CommandError,
+    jwt::{get_from_jwt, verify_jwt},
+    permissions::{verify_permission, AuthError},
+    settings::{get_setting, set_setting},
+    Database,
+};
+
+// amounts are kept in cents
+const DEFAULT_DAILY_RATE: i32 = 20;
+const DEFAULT_GRACE_DAYS: i32 = 0;
+const DEFAULT_CAP: i32 = 1000;
+const DEFAULT_BLOCK_THRESHOLD: i32 = 500;
+
+const FINE: &str = "multa";
+const PAYMENT: &str = "pagamento";
+const WAIVER: &str = "perdao";
+
+#[derive(Serialize, Deserialize)]
+pub struct FinePolicy {
+    pub daily_rate: i32,
+    pub grace_days: i32,
+    // 0 means fines are not capped
+    pub cap: i32,
+    // new loans are refused while the balance is above this
+    pub block_threshold: i32,
+}
+
+pub async fn load_fine_policy(conn: &Pool<MySql>) -> Result<FinePolicy, String> {
+    Ok(FinePolicy {
+        daily_rate: get_setting("multa_valor_diario", DEFAULT_DAILY_RATE, conn).await?,
+        grace_days: get_setting("multa_dias_tolerancia", DEFAULT_GRACE_DAYS, conn).await?,
+        cap: get_setting("multa_maximo", DEFAULT_CAP, conn).await?,
+        block_threshold: get_setting("multa_limite_bloqueio", DEFAULT_BLOCK_THRESHOLD, conn)
+            .await?,
+    })
+}
+
+impl FinePolicy {
+    // days within the grace period are not charged
+    // the ledger stores each entry as an INT, so an uncapped fine stops at its largest value
+    pub fn fine_for(&self, days_late: i64) -> i64 {
+        let charged_days = (days_late - self.grace_days as i64).max(0);
+        let fine = charged_days.saturating_mul(self.daily_rate as i64);
+
+        match self.cap {
+            0 => fine.min(i32::MAX as i64),
+            cap => fine.min(cap as i64),
+        }
+    }
+}
+
+pub async fn reader_balance(
+    reader_id: i32,
+    conn: &mut sqlx::MySqlConnection,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT CAST(COALESCE(SUM(valor), 0) AS SIGNED) FROM movimentos_leitor WHERE id_leitor = ?",
+    )
+    .bind(reader_id)
+    .fetch_one(conn)
+    .await
+}
+
+// charges the reader for a loan returned after its due date, runs inside the return transaction
+// and gives back the ledger entry id and the amount charged
+pub async fn charge_overdue_fine(
+    request_id: i32,
+    librarian_id: i32,
+    policy: &FinePolicy,
+    tx: &mut sqlx::MySqlConnection,
+) -> Result<Option<(i32, i64)>, sqlx::Error> {
+    let (reader_id, days_late): (i32, Option<i64>) = sqlx::query_as(
+        "SELECT id_leitor, CAST(DATEDIFF(data_entrega, data_limite) AS SIGNED) FROM requisicoes WHERE id = ?",
+    )
+    .bind(request_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let fine = policy.fine_for(days_late.unwrap_or_default());
+
+    if fine <= 0 {
+        return Ok(None);
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO movimentos_leitor (id_leitor, id_requisicao, id_bibliotecario, tipo, valor, descricao, data) VALUES (?, ?, ?, ?, ?, ?, NOW())",
+    )
+    .bind(reader_id)
+    .bind(request_id)
+    .bind(librarian_id)
+    .bind(FINE)
+    .bind(fine)
+    .bind(format!("Devolução com {} dia(s) de atraso", days_late.unwrap_or_default()))
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(Some((result.last_insert_id() as i32, fine)))
+}
+
+// payments and waivers are stored as negative entries so the balance is the sum of the ledger,
+// runs inside the transaction that also writes the audit entry
+async fn credit_reader(
+    token: &str,
+    reader_id: i32,
+    kind: &str,
+    amount: i32,
+    note: Option<String>,
+    tx: &mut sqlx::MySqlConnection,
+) -> Result<i32, String> {
+    if amount <= 0 {
+        return Err("Valor inválido".to_string());
+    }
+
+    let librarian_id = get_from_jwt(token)?
+        .get("id")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or("token inválido")?;
+
+    // locking the reader serializes concurrent payments against the same balance
+    let reader_exists: Option<i32> =
+        sqlx::query_scalar("SELECT id FROM leitores WHERE id = ? FOR UPDATE")
+            .bind(reader_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar leitor: {}", e);
+                format!("Falha ao consultar leitor: {}", e)
+            })?;
+
+    if reader_exists.is_none() {
+        return Err("Leitor não encontrado".to_string());
+    }
+
+    let balance = reader_balance(reader_id, &mut *tx).await.map_err(|e| {
+        tracing::error!("Falha ao consultar saldo: {}", e);
+        format!("Falha ao consultar saldo: {}", e)
+    })?;
+
+    if amount as i64 > balance {
+        return Err(format!(
+            "O valor excede o saldo em dívida do leitor ({:.2} €)",
+            balance as f64 / 100.0
+        ));
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO movimentos_leitor (id_leitor, id_bibliotecario, tipo, valor, descricao, data) VALUES (?, ?, ?, ?, ?, NOW())",
+    )
+    .bind(reader_id)
+    .bind(librarian_id)
+    .bind(kind)
+    .bind(-amount)
+    .bind(note)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao registar movimento: {}", e);
+        format!("Falha ao registar movimento: {}", e)
+    })?;
+
+    Ok(result.last_insert_id() as i32)
+}
+
+#[tauri::command]
+pub async fn get_fine_policy(
+    token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<FinePolicy, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    Ok(load_fine_policy(pool).await?)
+}
+
+#[tauri::command]
+pub async fn update_fine_policy(
+    token: String,
+    policy: FinePolicy,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    if policy.daily_rate < 0
+        || policy.grace_days < 0
+        || policy.cap < 0
+        || policy.block_threshold < 0
+    {
+        return Err("Política de multas inválida".to_string().into());
+    }
+
+    set_setting("multa_valor_diario", policy.daily_rate, pool).await?;
+    set_setting("multa_dias_tolerancia", policy.grace_days, pool).await?;
+    set_setting("multa_maximo", policy.cap, pool).await?;
+    set_setting("multa_limite_bloqueio", policy.block_threshold, pool).await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_reader_balance(
+    token: String,
+    reader_id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<i64, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let mut conn = pool.acquire().await.map_err(|e| {
+        tracing::error!("Falha ao obter ligação: {}", e);
+        format!("Falha ao obter ligação: {}", e)
+    })?;
+
+    Ok(reader_balance(reader_id, &mut conn).await.map_err(|e| {
+        tracing::error!("Falha ao consultar saldo: {}", e);
+        format!("Falha ao consultar saldo: {}", e)
+    })?)
+}
+
+#[tauri::command]
+pub async fn get_reader_ledger(
+    token: String,
+    reader_id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<MovimentoLeitor>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let entries = sqlx::query_as::<_, MovimentoLeitor>(
+        "SELECT * FROM movimentos_leitor WHERE id_leitor = ? ORDER BY data DESC, id DESC",
+    )
+    .bind(reader_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar movimentos: {}", e);
+        format!("Falha ao consultar movimentos: {}", e)
+    })?;
+
+    Ok(entries)
+}
+
+#[tauri::command]
+pub async fn record_payment(
+    token: String,
+    reader_id: i32,
+    amount: i32,
+    note: Option<String>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_requisicao", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let id = credit_reader(&token, reader_id, PAYMENT, amount, note, &mut tx).await?;
+    let after =
+        snapshot::<MovimentoLeitor>("SELECT * FROM movimentos_leitor WHERE id = ?", id, &mut tx)
+            .await?;
+
+    record_audit(
+        Some(&token),
+        CREATE,
+        "movimentos_leitor",
+        id,
+        None,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn waive_fine(
+    token: String,
+    reader_id: i32,
+    amount: i32,
+    note: Option<String>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "perdoar_multa", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let id = credit_reader(&token, reader_id, WAIVER, amount, note, &mut tx).await?;
+    let after =
+        snapshot::<MovimentoLeitor>("SELECT * FROM movimentos_leitor WHERE id = ?", id, &mut tx)
+            .await?;
+
+    record_audit(
+        Some(&token),
+        CREATE,
+        "movimentos_leitor",
+        id,
+        None,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(grace_days: i32, cap: i32) -> FinePolicy {
+        FinePolicy {
+            daily_rate: 20,
+            grace_days,
+            cap,
+            block_threshold: 500,
+        }
+    }
+
+    #[test]
+    fn grace_days_are_not_charged() {
+        let policy = policy(2, 0);
+
+        assert_eq!(policy.fine_for(-3), 0);
+        assert_eq!(policy.fine_for(0), 0);
+        assert_eq!(policy.fine_for(2), 0);
+        assert_eq!(policy.fine_for(3), 20);
+        assert_eq!(policy.fine_for(10), 160);
+    }
+
+    #[test]
+    fn fines_stop_at_the_cap() {
+        let capped = policy(0, 1000);
+
+        assert_eq!(capped.fine_for(49), 980);
+        assert_eq!(capped.fine_for(50), 1000);
+        assert_eq!(capped.fine_for(51), 1000);
+
+        // without a cap the fine only stops where an i32 does
+        let uncapped = policy(0, 0);
+
+        assert_eq!(uncapped.fine_for(365), 7300);
+        assert_eq!(uncapped.fine_for(i64::MAX), i32::MAX as i64);
+    }
+}
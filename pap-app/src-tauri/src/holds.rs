@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::{
+    audit::{record_audit, snapshot, CREATE, UPDATE},
+    copies::count_copies,
+    db_structs::Reserva,
+    errors::CommandError,
+    jwt::verify_jwt,
+    loans::load_loan_policy,
+    permissions::{verify_permission, AuthError},
+    Database,
+};
+
+// a hold waits in the queue as pendente until a copy is set aside for it as disponivel
+const HOLDS_QUERY: &str = "SELECT reservas.*, leitores.nome AS leitor, livros.nome AS livro,
+    CASE WHEN reservas.estado = 'pendente' THEN (
+        SELECT COUNT(*) FROM reservas anteriores
+        WHERE anteriores.id_livro = reservas.id_livro AND anteriores.estado = 'pendente'
+        AND (anteriores.data_reserva, anteriores.id) <= (reservas.data_reserva, reservas.id)
+    ) END AS posicao
+    FROM reservas
+    INNER JOIN leitores ON leitores.id = reservas.id_leitor
+    INNER JOIN livros ON livros.id = reservas.id_livro";
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReservaAsResponse {
+    pub id: i32,
+    pub id_livro: i32,
+    pub livro: String,
+    pub id_leitor: i32,
+    pub leitor: String,
+    pub estado: String,
+    pub posicao: Option<i64>,
+    pub data_reserva: chrono::DateTime<chrono::Utc>,
+    pub data_disponivel: Option<chrono::DateTime<chrono::Utc>>,
+    pub data_expiracao: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// sets free copies aside for the oldest holds in the queue
+pub async fn promote_holds(book_id: i32, conn: &Pool<MySql>) -> Result<(), String> {
+    let pickup_days = load_loan_policy(conn).await?.hold_pickup_days;
+
+    let mut tx = conn.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    // locked like in lend_copy, so a loan can not take a copy this is setting aside
+    sqlx::query("SELECT id FROM exemplares WHERE id_livro = ? FOR UPDATE")
+        .bind(book_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar exemplares: {}", e);
+            format!("Falha ao consultar exemplares: {}", e)
+        })?;
+
+    let (_, available) = count_copies(book_id, &mut tx).await?;
+
+    let ready: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM reservas WHERE id_livro = ? AND estado = 'disponivel'",
+    )
+    .bind(book_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar reservas: {}", e);
+        format!("Falha ao consultar reservas: {}", e)
+    })?;
+
+    let free = available - ready;
+
+    if free <= 0 {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "UPDATE reservas SET estado = 'disponivel', data_disponivel = NOW(), data_expiracao = DATE_ADD(NOW(), INTERVAL ? DAY)
+        WHERE id_livro = ? AND estado = 'pendente'
+        ORDER BY data_reserva, id LIMIT ?",
+    )
+    .bind(pickup_days)
+    .bind(book_id)
+    .bind(free)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao atualizar reservas: {}", e);
+        format!("Falha ao atualizar reservas: {}", e)
+    })?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+// holds not collected within the pickup window expire and their copies go to the next in line
+pub async fn expire_holds(conn: &Pool<MySql>) -> Result<(), String> {
+    let books: Vec<i32> = sqlx::query_scalar(
+        "SELECT DISTINCT id_livro FROM reservas WHERE estado = 'disponivel' AND data_expiracao < NOW()",
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar reservas: {}", e);
+        format!("Falha ao consultar reservas: {}", e)
+    })?;
+
+    if books.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "UPDATE reservas SET estado = 'expirada' WHERE estado = 'disponivel' AND data_expiracao < NOW()",
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao expirar reservas: {}", e);
+        format!("Falha ao expirar reservas: {}", e)
+    })?;
+
+    for book_id in books {
+        promote_holds(book_id, conn).await?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn place_hold(
+    token: String,
+    book_id: i32,
+    reader_id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "criar_requisicao", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    expire_holds(pool).await?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let (reader_exists, book_exists, has_hold, has_loan): (bool, bool, bool, bool) = sqlx::query_as(
+        "SELECT EXISTS(SELECT * FROM leitores WHERE id = ?),
+            EXISTS(SELECT * FROM livros WHERE id = ?),
+            EXISTS(SELECT * FROM reservas WHERE id_livro = ? AND id_leitor = ? AND estado IN ('pendente', 'disponivel')),
+            EXISTS(SELECT * FROM requisicoes WHERE id_livro_requisitado = ? AND id_leitor = ? AND data_entrega IS NULL)",
+    )
+    .bind(reader_id)
+    .bind(book_id)
+    .bind(book_id)
+    .bind(reader_id)
+    .bind(book_id)
+    .bind(reader_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar reservas: {}", e);
+        format!("Falha ao consultar reservas: {}", e)
+    })?;
+
+    if !reader_exists {
+        return Err("Leitor não encontrado".to_string().into());
+    }
+
+    if !book_exists {
+        return Err("Livro não encontrado".to_string().into());
+    }
+
+    if has_hold {
+        return Err("O leitor já tem este livro reservado".to_string().into());
+    }
+
+    if has_loan {
+        return Err("O leitor já tem este livro requisitado".to_string().into());
+    }
+
+    // locked like in lend_copy, so the copies can not be lent while the hold is checked
+    sqlx::query("SELECT id FROM exemplares WHERE id_livro = ? FOR UPDATE")
+        .bind(book_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar exemplares: {}", e);
+            format!("Falha ao consultar exemplares: {}", e)
+        })?;
+
+    let (total, available) = count_copies(book_id, &mut tx).await?;
+
+    if total == 0 {
+        return Err("Este livro não tem exemplares".to_string().into());
+    }
+
+    let ready: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM reservas WHERE id_livro = ? AND estado = 'disponivel'",
+    )
+    .bind(book_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar reservas: {}", e);
+        format!("Falha ao consultar reservas: {}", e)
+    })?;
+
+    if available > ready {
+        return Err("Existem exemplares disponíveis, requisite o livro"
+            .to_string()
+            .into());
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO reservas (id_livro, id_leitor, estado, data_reserva) VALUES (?, ?, 'pendente', NOW())",
+    )
+    .bind(book_id)
+    .bind(reader_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao criar reserva: {}", e);
+        format!("Falha ao criar reserva: {}", e)
+    })?;
+
+    let id = result.last_insert_id() as i32;
+    let after = snapshot::<Reserva>("SELECT * FROM reservas WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "reservas", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn cancel_hold(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_requisicao", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let hold = sqlx::query_as::<_, Reserva>("SELECT * FROM reservas WHERE id = ? FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar reserva: {}", e);
+            format!("Falha ao consultar reserva: {}", e)
+        })?
+        .ok_or("Reserva não encontrada")?;
+
+    if hold.estado != "pendente" && hold.estado != "disponivel" {
+        return Err("A reserva já não está ativa".to_string().into());
+    }
+
+    let before = snapshot::<Reserva>("SELECT * FROM reservas WHERE id = ?", id, &mut tx).await?;
+
+    sqlx::query("UPDATE reservas SET estado = 'cancelada' WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao cancelar reserva: {}", e);
+            format!("Falha ao cancelar reserva: {}", e)
+        })?;
+
+    let after = snapshot::<Reserva>("SELECT * FROM reservas WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), UPDATE, "reservas", id, before, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    // the copy set aside for this hold goes to the next reader in the queue
+    if hold.estado == "disponivel" {
+        promote_holds(hold.id_livro, pool).await?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_holds_by_book_id(
+    token: String,
+    book_id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<ReservaAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    expire_holds(pool).await?;
+
+    let holds = sqlx::query_as::<_, ReservaAsResponse>(&format!(
+        "{} WHERE reservas.id_livro = ? AND reservas.estado IN ('pendente', 'disponivel')
+        ORDER BY reservas.estado = 'pendente', reservas.data_reserva, reservas.id",
+        HOLDS_QUERY
+    ))
+    .bind(book_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar reservas: {}", e);
+        format!("Falha ao consultar reservas: {}", e)
+    })?;
+
+    Ok(holds)
+}
+
+#[tauri::command]
+pub async fn get_holds_by_reader_id(
+    token: String,
+    reader_id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<ReservaAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    expire_holds(pool).await?;
+
+    let holds = sqlx::query_as::<_, ReservaAsResponse>(&format!(
+        "{} WHERE reservas.id_leitor = ? ORDER BY reservas.data_reserva DESC, reservas.id DESC",
+        HOLDS_QUERY
+    ))
+    .bind(reader_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar reservas: {}", e);
+        format!("Falha ao consultar reservas: {}", e)
+    })?;
+
+    Ok(holds)
+}
@@ -0,0 +1,184 @@
+use serde::Serialize;
+
+use crate::barcodes::{ean13_check_digit, is_valid_ean13};
+
+// books printed before 2007 only carry the 10 digit form, the 13 digit one is the bookland EAN
+#[derive(Serialize, Clone)]
+pub struct Isbn {
+    pub isbn10: Option<String>,
+    pub isbn13: String,
+}
+
+// weights go from 10 down to 2 and a check value of 10 is written as X
+fn isbn10_check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .take(9)
+        .enumerate()
+        .map(|(position, digit)| *digit as u32 * (10 - position as u32))
+        .sum();
+
+    ((11 - sum % 11) % 11) as u8
+}
+
+fn isbn10_check_char(check: u8) -> char {
+    match check {
+        10 => 'X',
+        digit => (b'0' + digit) as char,
+    }
+}
+
+pub fn is_valid_isbn10(code: &str) -> bool {
+    let bytes = code.as_bytes();
+
+    if bytes.len() != 10 || !bytes[..9].iter().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+
+    let digits: Vec<u8> = bytes[..9].iter().map(|c| c - b'0').collect();
+
+    isbn10_check_char(isbn10_check_digit(&digits)) == bytes[9] as char
+}
+
+pub fn is_valid_isbn13(code: &str) -> bool {
+    is_valid_ean13(code) && (code.starts_with("978") || code.starts_with("979"))
+}
+
+pub fn isbn10_to_isbn13(code: &str) -> String {
+    let digits: Vec<u8> = "978"
+        .bytes()
+        .chain(code.bytes().take(9))
+        .map(|c| c - b'0')
+        .collect();
+
+    format!("978{}{}", &code[..9], ean13_check_digit(&digits))
+}
+
+// only the 978 prefix has a 10 digit equivalent
+pub fn isbn13_to_isbn10(code: &str) -> Option<String> {
+    if !code.starts_with("978") {
+        return None;
+    }
+
+    let digits: Vec<u8> = code[3..12].bytes().map(|c| c - b'0').collect();
+
+    Some(format!(
+        "{}{}",
+        &code[3..12],
+        isbn10_check_char(isbn10_check_digit(&digits))
+    ))
+}
+
+// ISBNs are usually written with hyphens or spaces between the groups, which are dropped
+pub fn parse_isbn(input: &str) -> Result<Isbn, String> {
+    let code: String = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_uppercase();
+
+    let digits = code.bytes().filter(|c| c.is_ascii_digit()).count();
+
+    let valid = match (code.len(), digits) {
+        (10, 10) => is_valid_isbn10(&code),
+        (10, 9) if code.ends_with('X') => is_valid_isbn10(&code),
+        (13, 13) if code.starts_with("978") || code.starts_with("979") => is_valid_isbn13(&code),
+        _ => return Err(format!("O ISBN {} não é válido", input.trim())),
+    };
+
+    if !valid {
+        return Err(format!(
+            "O ISBN {} tem um dígito de controlo inválido",
+            input.trim()
+        ));
+    }
+
+    if code.len() == 10 {
+        Ok(Isbn {
+            isbn13: isbn10_to_isbn13(&code),
+            isbn10: Some(code),
+        })
+    } else {
+        Ok(Isbn {
+            isbn10: isbn13_to_isbn10(&code),
+            isbn13: code,
+        })
+    }
+}
+
+// blank input means the book has no ISBN
+pub fn parse_optional_isbn(input: Option<String>) -> Result<Option<Isbn>, String> {
+    match input {
+        Some(input) if !input.trim().is_empty() => Ok(Some(parse_isbn(&input)?)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isbn10_check_digits() {
+        assert!(is_valid_isbn10("0306406152"));
+        assert!(!is_valid_isbn10("0306406153"));
+        // a check value of 10 is written as X
+        assert!(is_valid_isbn10("080442957X"));
+        assert!(!is_valid_isbn10("0804429570"));
+        assert!(!is_valid_isbn10("X306406152"));
+    }
+
+    #[test]
+    fn isbn13_needs_a_bookland_prefix() {
+        assert!(is_valid_isbn13("9780306406157"));
+        assert!(is_valid_isbn13("9791090636071"));
+        assert!(!is_valid_isbn13("9780306406158"));
+        // a valid EAN-13 that is not an ISBN
+        assert!(!is_valid_isbn13("4006381333931"));
+    }
+
+    #[test]
+    fn parses_both_lengths_and_drops_separators() {
+        let isbn = parse_isbn(" 0-306-40615-2 ").unwrap();
+        assert_eq!(isbn.isbn10.as_deref(), Some("0306406152"));
+        assert_eq!(isbn.isbn13, "9780306406157");
+
+        let isbn = parse_isbn("0 8044 2957 x").unwrap();
+        assert_eq!(isbn.isbn10.as_deref(), Some("080442957X"));
+        assert_eq!(isbn.isbn13, "9780804429573");
+
+        let isbn = parse_isbn("978-0-306-40615-7").unwrap();
+        assert_eq!(isbn.isbn10.as_deref(), Some("0306406152"));
+        assert_eq!(isbn.isbn13, "9780306406157");
+
+        // 979 numbers have no 10 digit form
+        let isbn = parse_isbn("979-10-90636-07-1").unwrap();
+        assert_eq!(isbn.isbn10, None);
+        assert_eq!(isbn.isbn13, "9791090636071");
+    }
+
+    #[test]
+    fn rejects_malformed_isbns() {
+        assert!(parse_isbn("0-306-40615-3").is_err());
+        assert!(parse_isbn("978-0-306-40615-8").is_err());
+        assert!(parse_isbn("4006381333931").is_err());
+        assert!(parse_isbn("03064061").is_err());
+        assert!(parse_isbn("03064061X2").is_err());
+        assert!(parse_optional_isbn(Some("  ".to_string()))
+            .unwrap()
+            .is_none());
+        assert!(parse_optional_isbn(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn converts_between_lengths_and_back() {
+        for isbn10 in ["0306406152", "080442957X", "0198534531"] {
+            let isbn13 = isbn10_to_isbn13(isbn10);
+
+            assert!(is_valid_isbn13(&isbn13), "{}", isbn13);
+            assert_eq!(isbn13_to_isbn10(&isbn13).as_deref(), Some(isbn10));
+        }
+
+        assert_eq!(isbn13_to_isbn10("9791090636071"), None);
+    }
+}
@@ -1,58 +1,486 @@
+use std::{collections::BTreeMap, sync::RwLock};
+
 use hmac::{Hmac, Mac};
-use jwt::{SignWithKey, VerifyWithKey};
-use sha2::Sha256;
+use jwt::{AlgorithmType, Header, SignWithKey, Token, VerifyWithKey};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
 use sqlx::{MySql, Pool};
 
-use crate::{db_structs::Bibliotecario, SECRET};
+use crate::{
+    db_structs::{Bibliotecario, ChaveJwt, Sessao},
+    errors::CommandError,
+    permissions::verify_permission,
+    Database,
+};
 
-pub async fn verify_jwt(token: &str, conn: &Pool<MySql>) -> Result<(), String> {
-    let key: Hmac<Sha256> = Hmac::new_from_slice(SECRET.as_bytes()).unwrap();
-    let claims: Result<std::collections::BTreeMap<String, String>, _> = token.verify_with_key(&key);
+// how long the previous keys keep verifying tokens after a rotation
+const DEFAULT_ROTATION_WINDOW_HOURS: i64 = 24;
+const ACCESS_TOKEN_MINUTES: i64 = 15;
+const REFRESH_TOKEN_DAYS: i64 = 7;
+const CHALLENGE_TOKEN_MINUTES: i64 = 5;
+pub const TOKEN_EXPIRED: &str = "token expirado";
 
-    if claims.is_err() {
-        return Err("token inválido".to_string());
+struct KeyRing {
+    active: Option<String>,
+    keys: BTreeMap<String, ChaveJwt>,
+}
+
+lazy_static::lazy_static! {
+    static ref KEYS: RwLock<KeyRing> = RwLock::new(KeyRing {
+        active: None,
+        keys: BTreeMap::new(),
+    });
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn insert_new_key(conn: &mut sqlx::MySqlConnection) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO chaves_jwt (id, segredo, ativa, data_criacao) VALUES (?, ?, TRUE, NOW())",
+    )
+    .bind(random_hex(16))
+    .bind(random_hex(64))
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao criar chave: {}", e);
+        format!("Falha ao criar chave: {}", e)
+    })?;
+
+    Ok(())
+}
+
+pub async fn load_keys(conn: &Pool<MySql>) -> Result<(), String> {
+    let active_count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM chaves_jwt WHERE ativa")
+        .fetch_one(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar chaves: {}", e);
+            format!("Falha ao consultar chaves: {}", e)
+        })?;
+
+    if active_count == 0 {
+        let mut db_conn = conn.acquire().await.map_err(|e| {
+            tracing::error!("Falha ao obter conexão: {}", e);
+            format!("Falha ao obter conexão: {}", e)
+        })?;
+
+        insert_new_key(&mut db_conn).await?;
+    }
+
+    let keys = sqlx::query_as::<_, ChaveJwt>(
+        "SELECT id, segredo, ativa, data_expiracao FROM chaves_jwt WHERE ativa OR data_expiracao > NOW() ORDER BY data_criacao",
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar chaves: {}", e);
+        format!("Falha ao consultar chaves: {}", e)
+    })?;
+
+    let mut ring = KEYS
+        .write()
+        .map_err(|_| "Falha ao carregar chaves".to_string())?;
+
+    ring.active = keys.iter().rev().find(|k| k.ativa).map(|k| k.id.clone());
+    ring.keys = keys.into_iter().map(|k| (k.id.clone(), k)).collect();
+
+    Ok(())
+}
+
+// checks the token and its session without requiring the password to be up to date
+pub async fn verify_session(token: &str, conn: &Pool<MySql>) -> Result<Bibliotecario, String> {
+    let claims = match get_from_jwt(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            // the key may have been created or rotated by another client since we last loaded them
+            load_keys(conn).await?;
+            get_from_jwt(token)?
+        }
+    };
+
+    let expiration = claims
+        .get("exp")
+        .and_then(|exp| exp.parse::<i64>().ok())
+        .ok_or("token inválido")?;
+
+    if expiration <= chrono::Utc::now().timestamp() {
+        return Err(TOKEN_EXPIRED.to_string());
     }
 
-    let claims = claims.unwrap();
+    let id = claims
+        .get("id")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or("token inválido")?;
 
-    let id = claims.get("id").unwrap().parse::<i32>().unwrap();
+    let session_id = claims.get("sid").ok_or("token inválido")?;
 
     let librarian = sqlx::query_as::<_, Bibliotecario>("SELECT * FROM bibliotecarios WHERE id = ?")
         .bind(id)
-        .fetch_all(conn)
+        .fetch_optional(conn)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
             format!("Falha ao consultar: {}", e)
-        })?;
+        })?
+        .ok_or("Nenhum bibliotecário encontrado")?;
+
+    let active_session: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT * FROM sessoes WHERE id = ? AND id_bibliotecario = ? AND NOT revogada AND data_expiracao > NOW())",
+    )
+    .bind(session_id)
+    .bind(id)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar sessão: {}", e);
+        format!("Falha ao consultar sessão: {}", e)
+    })?;
 
-    if librarian.is_empty() {
-        return Err("Nenhum bibliotecário encontrado".to_string());
+    if !active_session {
+        return Err("sessão terminada".to_string());
+    }
+
+    Ok(librarian)
+}
+
+pub async fn verify_jwt(token: &str, conn: &Pool<MySql>) -> Result<(), String> {
+    let librarian = verify_session(token, conn).await?;
+
+    if librarian.alterar_password {
+        return Err("É necessário alterar a password".to_string());
+    }
+
+    let claims = get_from_jwt(token)?;
+
+    if claims.contains_key("totp") {
+        return Err("É necessário configurar a autenticação de dois fatores".to_string());
     }
 
     Ok(())
 }
 
-pub fn new_jwt(librarian: &Bibliotecario) -> String {
-    let key: Hmac<Sha256> = Hmac::new_from_slice(SECRET.as_bytes()).unwrap();
-    let mut claims = std::collections::BTreeMap::new();
+fn sign_claims(claims: BTreeMap<&str, &str>) -> Result<String, String> {
+    let ring = KEYS.read().map_err(|_| "Falha ao ler chaves".to_string())?;
+
+    let key_id = ring
+        .active
+        .clone()
+        .ok_or("Nenhuma chave de assinatura ativa")?;
+    let secret = &ring
+        .keys
+        .get(&key_id)
+        .ok_or("Chave de assinatura não encontrada")?
+        .segredo;
+
+    let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes()).unwrap();
+
+    let header = Header {
+        algorithm: AlgorithmType::Hs256,
+        key_id: Some(key_id),
+        ..Default::default()
+    };
+
+    let token = Token::new(header, claims)
+        .sign_with_key(&key)
+        .map_err(|e| {
+            tracing::error!("Falha ao assinar token: {}", e);
+            format!("Falha ao assinar token: {}", e)
+        })?;
+
+    Ok(token.as_str().to_string())
+}
+
+// tokens with a pending enrollment are only accepted by verify_session
+pub fn new_jwt(
+    librarian: &Bibliotecario,
+    session_id: &str,
+    totp_enrollment_pending: bool,
+) -> Result<String, String> {
+    let mut claims: BTreeMap<&str, &str> = BTreeMap::new();
 
     let id_as_string = &librarian.id.to_string();
+    let now = chrono::Utc::now().timestamp();
+    let issued_at = &now.to_string();
+    let expiration = &(now + ACCESS_TOKEN_MINUTES * 60).to_string();
 
     claims.insert("id", id_as_string);
     claims.insert("name", &librarian.nome);
     claims.insert("role", &librarian.cargo);
+    claims.insert("sid", session_id);
+    claims.insert("iat", issued_at);
+    claims.insert("exp", expiration);
+
+    if totp_enrollment_pending {
+        claims.insert("totp", "pendente");
+    }
+
+    sign_claims(claims)
+}
+
+// proves the password was correct while the second factor is still missing,
+// it has no session so it is never accepted where a normal token is expected
+pub fn new_challenge_jwt(librarian_id: i32) -> Result<String, String> {
+    let mut claims: BTreeMap<&str, &str> = BTreeMap::new();
+
+    let id_as_string = &librarian_id.to_string();
+    let expiration = &(chrono::Utc::now().timestamp() + CHALLENGE_TOKEN_MINUTES * 60).to_string();
+
+    claims.insert("id", id_as_string);
+    claims.insert("purpose", "totp");
+    claims.insert("exp", expiration);
+
+    sign_claims(claims)
+}
+
+pub async fn verify_challenge_jwt(token: &str, conn: &Pool<MySql>) -> Result<i32, String> {
+    let claims = match get_from_jwt(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            load_keys(conn).await?;
+            get_from_jwt(token)?
+        }
+    };
+
+    if claims.get("purpose").map(String::as_str) != Some("totp") {
+        return Err("token inválido".to_string());
+    }
+
+    let expiration = claims
+        .get("exp")
+        .and_then(|exp| exp.parse::<i64>().ok())
+        .ok_or("token inválido")?;
+
+    if expiration <= chrono::Utc::now().timestamp() {
+        return Err("O pedido de login expirou, inicie sessão novamente".to_string());
+    }
 
-    claims.sign_with_key(&key).unwrap()
+    claims
+        .get("id")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or("token inválido".to_string())
 }
 
-pub fn get_from_jwt(token: &str) -> Result<std::collections::BTreeMap<String, String>, String> {
-    let key: Hmac<Sha256> = Hmac::new_from_slice(SECRET.as_bytes()).unwrap();
-    let claims: Result<std::collections::BTreeMap<String, String>, _> = token.verify_with_key(&key);
+pub fn get_from_jwt(token: &str) -> Result<BTreeMap<String, String>, String> {
+    let unverified: Token<Header, BTreeMap<String, String>, _> =
+        Token::parse_unverified(token).map_err(|_| "token inválido".to_string())?;
 
-    if claims.is_err() {
+    let key_id = unverified.header().key_id.clone().ok_or("token inválido")?;
+
+    let ring = KEYS.read().map_err(|_| "Falha ao ler chaves".to_string())?;
+    let stored_key = ring.keys.get(&key_id).ok_or("token inválido")?;
+
+    let expired = match stored_key.data_expiracao {
+        Some(expiration) => expiration <= chrono::Utc::now(),
+        None => true,
+    };
+
+    if !stored_key.ativa && expired {
         return Err("token inválido".to_string());
     }
 
-    Ok(claims.unwrap())
+    let key: Hmac<Sha256> = Hmac::new_from_slice(stored_key.segredo.as_bytes()).unwrap();
+    let verified: Result<Token<Header, BTreeMap<String, String>, _>, _> =
+        unverified.verify_with_key(&key);
+
+    match verified {
+        Ok(verified) => Ok(verified.claims().clone()),
+        Err(_) => Err("token inválido".to_string()),
+    }
+}
+
+fn hash_refresh_token(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// refresh tokens are "<session id>.<secret>", only the hash of the secret is stored
+pub async fn create_session(
+    librarian_id: i32,
+    conn: &Pool<MySql>,
+) -> Result<(String, String), String> {
+    let session_id = random_hex(16);
+    let secret = random_hex(32);
+
+    sqlx::query(
+        "INSERT INTO sessoes (id, id_bibliotecario, refresh_token, data_criacao, data_expiracao) VALUES (?, ?, ?, NOW(), DATE_ADD(NOW(), INTERVAL ? DAY))",
+    )
+    .bind(&session_id)
+    .bind(librarian_id)
+    .bind(hash_refresh_token(&secret))
+    .bind(REFRESH_TOKEN_DAYS)
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao criar sessão: {}", e);
+        format!("Falha ao criar sessão: {}", e)
+    })?;
+
+    let refresh_token = format!("{}.{}", session_id, secret);
+
+    Ok((session_id, refresh_token))
+}
+
+pub async fn refresh_session(
+    refresh_token: &str,
+    conn: &Pool<MySql>,
+) -> Result<(Sessao, String), String> {
+    let (session_id, secret) = refresh_token
+        .split_once('.')
+        .ok_or("token de sessão inválido")?;
+
+    let session = sqlx::query_as::<_, Sessao>(
+        "SELECT id, id_bibliotecario, refresh_token FROM sessoes WHERE id = ? AND NOT revogada AND data_expiracao > NOW()",
+    )
+    .bind(session_id)
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar sessão: {}", e);
+        format!("Falha ao consultar sessão: {}", e)
+    })?
+    .ok_or("sessão terminada")?;
+
+    if session.refresh_token != hash_refresh_token(secret) {
+        // a reused refresh token means it leaked, so the whole session is dropped
+        revoke_session(session_id, conn).await?;
+        return Err("token de sessão inválido".to_string());
+    }
+
+    let new_secret = random_hex(32);
+
+    // only one of two refreshes racing with the same token swaps it, the other one gets nothing
+    let result = sqlx::query(
+        "UPDATE sessoes SET refresh_token = ? WHERE id = ? AND refresh_token = ? AND NOT revogada",
+    )
+    .bind(hash_refresh_token(&new_secret))
+    .bind(session_id)
+    .bind(&session.refresh_token)
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao atualizar sessão: {}", e);
+        format!("Falha ao atualizar sessão: {}", e)
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err("token de sessão inválido".to_string());
+    }
+
+    let new_refresh_token = format!("{}.{}", session_id, new_secret);
+
+    Ok((session, new_refresh_token))
+}
+
+pub async fn revoke_session(session_id: &str, conn: &Pool<MySql>) -> Result<(), String> {
+    sqlx::query("UPDATE sessoes SET revogada = TRUE WHERE id = ?")
+        .bind(session_id)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao terminar sessão: {}", e);
+            format!("Falha ao terminar sessão: {}", e)
+        })?;
+
+    Ok(())
+}
+
+pub async fn revoke_librarian_sessions(
+    librarian_id: i32,
+    conn: &mut sqlx::MySqlConnection,
+) -> Result<(), String> {
+    sqlx::query("UPDATE sessoes SET revogada = TRUE WHERE id_bibliotecario = ?")
+        .bind(librarian_id)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao terminar sessões: {}", e);
+            format!("Falha ao terminar sessões: {}", e)
+        })?;
+
+    Ok(())
+}
+
+pub async fn revoke_other_sessions(
+    librarian_id: i32,
+    session_id: &str,
+    conn: &mut sqlx::MySqlConnection,
+) -> Result<(), String> {
+    sqlx::query("UPDATE sessoes SET revogada = TRUE WHERE id_bibliotecario = ? AND id <> ?")
+        .bind(librarian_id)
+        .bind(session_id)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao terminar sessões: {}", e);
+            format!("Falha ao terminar sessões: {}", e)
+        })?;
+
+    Ok(())
+}
+
+pub async fn revoke_role_sessions(
+    role: &str,
+    conn: &mut sqlx::MySqlConnection,
+) -> Result<(), String> {
+    sqlx::query(
+        "UPDATE sessoes SET revogada = TRUE WHERE id_bibliotecario IN (SELECT id FROM bibliotecarios WHERE cargo = ?)",
+    )
+    .bind(role)
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao terminar sessões: {}", e);
+        format!("Falha ao terminar sessões: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn rotate_jwt_key(
+    token: String,
+    window_hours: Option<i64>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    sqlx::query(
+        "UPDATE chaves_jwt SET ativa = FALSE, data_expiracao = DATE_ADD(NOW(), INTERVAL ? HOUR) WHERE ativa",
+    )
+    .bind(window_hours.unwrap_or(DEFAULT_ROTATION_WINDOW_HOURS))
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao desativar chaves: {}", e);
+        format!("Falha ao desativar chaves: {}", e)
+    })?;
+
+    insert_new_key(&mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(load_keys(pool).await?)
 }
@@ -0,0 +1,452 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::{
+    barcodes::code128_modules,
+    errors::CommandError,
+    jwt::verify_jwt,
+    pdf::{fit_text, mm, text_width, PdfDocument, PdfPage},
+    permissions::AuthError,
+    qrcode::QrCode,
+    Database,
+};
+
+// Code 128 needs ten modules of blank space on each side and QR codes need four
+const CODE128_QUIET_ZONE: usize = 10;
+const QR_QUIET_ZONE: usize = 4;
+// narrower bars than this are not read reliably by handheld scanners
+const MIN_MODULE_WIDTH_MM: f64 = 0.19;
+const LABEL_PADDING_MM: f64 = 2.0;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelFormat {
+    Code128,
+    Qr,
+}
+
+// every measure is in millimetres, the default is an A4 sheet of 3x8 labels (Avery L7159)
+#[derive(Serialize, Deserialize)]
+pub struct LabelLayout {
+    pub page_width: f64,
+    pub page_height: f64,
+    pub columns: usize,
+    pub rows: usize,
+    pub margin_top: f64,
+    pub margin_left: f64,
+    pub label_width: f64,
+    pub label_height: f64,
+    pub gap_x: f64,
+    pub gap_y: f64,
+}
+
+impl Default for LabelLayout {
+    fn default() -> Self {
+        LabelLayout {
+            page_width: 210.0,
+            page_height: 297.0,
+            columns: 3,
+            rows: 8,
+            margin_top: 12.9,
+            margin_left: 6.4,
+            label_width: 63.5,
+            label_height: 33.9,
+            gap_x: 2.5,
+            gap_y: 0.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LabelOptions {
+    pub format: LabelFormat,
+    #[serde(default)]
+    pub layout: LabelLayout,
+    // labels already used on the first sheet
+    #[serde(default)]
+    pub skip: usize,
+}
+
+#[derive(sqlx::FromRow)]
+struct Label {
+    id: i32,
+    code: String,
+    text: String,
+}
+
+// skip only counts labels of the first sheet, so it has to leave at least one free on it
+fn validate_layout(layout: &LabelLayout, skip: usize) -> Result<(), String> {
+    let measures = [
+        layout.page_width,
+        layout.page_height,
+        layout.label_width,
+        layout.label_height,
+    ];
+    let spacing = [
+        layout.margin_top,
+        layout.margin_left,
+        layout.gap_x,
+        layout.gap_y,
+    ];
+
+    if layout.columns == 0
+        || layout.rows == 0
+        || measures
+            .iter()
+            .any(|measure| !measure.is_finite() || *measure <= 0.0)
+        || spacing
+            .iter()
+            .any(|measure| !measure.is_finite() || *measure < 0.0)
+    {
+        return Err("Grelha de etiquetas inválida".to_string());
+    }
+
+    let width = layout.margin_left
+        + layout.columns as f64 * layout.label_width
+        + (layout.columns - 1) as f64 * layout.gap_x;
+    let height = layout.margin_top
+        + layout.rows as f64 * layout.label_height
+        + (layout.rows - 1) as f64 * layout.gap_y;
+
+    // a little tolerance for sheets whose measures are rounded
+    if width > layout.page_width + 0.5 || height > layout.page_height + 0.5 {
+        return Err("As etiquetas não cabem na página".to_string());
+    }
+
+    if skip >= layout.columns * layout.rows {
+        return Err(format!(
+            "A folha só tem {} etiquetas, não é possível saltar {}",
+            layout.columns * layout.rows,
+            skip
+        ));
+    }
+
+    Ok(())
+}
+
+// the code is drawn between the text line on top and the code itself written underneath
+fn draw_code128(
+    page: &mut PdfPage,
+    label: &Label,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+) -> Result<(), String> {
+    let modules = code128_modules(&label.code)?;
+    let module_width = width / (modules.len() + CODE128_QUIET_ZONE * 2) as f64;
+
+    if module_width < mm(MIN_MODULE_WIDTH_MM) {
+        return Err(format!("O código {} não cabe na etiqueta", label.code));
+    }
+
+    let text_size = (height * 0.16).clamp(5.0, 10.0);
+    let code_size = text_size * 0.8;
+    let bars_bottom = y + code_size + mm(1.0);
+    let bars_height = height - text_size - code_size - mm(2.0);
+
+    if bars_height <= 0.0 {
+        return Err("As etiquetas são demasiado pequenas".to_string());
+    }
+
+    let mut start = None;
+
+    for (i, bar) in modules.iter().chain([false].iter()).enumerate() {
+        match (bar, start) {
+            (true, None) => start = Some(i),
+            (false, Some(first)) => {
+                page.rect(
+                    x + (CODE128_QUIET_ZONE + first) as f64 * module_width,
+                    bars_bottom,
+                    (i - first) as f64 * module_width,
+                    bars_height,
+                );
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let text = fit_text(&label.text, text_size, width);
+    page.text(
+        x + (width - text_width(&text, text_size)) / 2.0,
+        y + height - text_size,
+        text_size,
+        &text,
+    );
+    page.text(
+        x + (width - text_width(&label.code, code_size)) / 2.0,
+        y,
+        code_size,
+        &label.code,
+    );
+
+    Ok(())
+}
+
+// the symbol sits on the left with the text and the code written beside it
+fn draw_qr(
+    page: &mut PdfPage,
+    label: &Label,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+) -> Result<(), String> {
+    let qr = QrCode::encode(label.code.as_bytes())?;
+    let side = height.min(width * 0.45);
+    let module_size = side / (qr.size + QR_QUIET_ZONE * 2) as f64;
+
+    if module_size < mm(MIN_MODULE_WIDTH_MM) {
+        return Err(format!("O código {} não cabe na etiqueta", label.code));
+    }
+
+    let bottom = y + (height - side) / 2.0;
+
+    for row in 0..qr.size {
+        let top = bottom + side - (QR_QUIET_ZONE + row + 1) as f64 * module_size;
+        let mut start = None;
+
+        for column in 0..=qr.size {
+            let dark = column < qr.size && qr.module(column, row);
+
+            match (dark, start) {
+                (true, None) => start = Some(column),
+                (false, Some(first)) => {
+                    page.rect(
+                        x + (QR_QUIET_ZONE + first) as f64 * module_size,
+                        top,
+                        (column - first) as f64 * module_size,
+                        module_size,
+                    );
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let text_x = x + side + mm(1.0);
+    let text_width = width - side - mm(1.0);
+    let text_size = (height * 0.16).clamp(5.0, 10.0);
+    let code_size = text_size * 0.8;
+
+    page.text(
+        text_x,
+        y + height / 2.0 + mm(1.0),
+        text_size,
+        &fit_text(&label.text, text_size, text_width),
+    );
+    page.text(
+        text_x,
+        y + height / 2.0 - code_size - mm(1.0),
+        code_size,
+        &fit_text(&label.code, code_size, text_width),
+    );
+
+    Ok(())
+}
+
+// labels fill the sheet row by row, after the ones already used on the first page
+fn render_labels(labels: &[Label], options: &LabelOptions) -> Result<Vec<u8>, String> {
+    let layout = &options.layout;
+    validate_layout(layout, options.skip)?;
+
+    let per_page = layout.columns * layout.rows;
+    let padding = mm(LABEL_PADDING_MM);
+    let mut document = PdfDocument::new(mm(layout.page_width), mm(layout.page_height));
+
+    for (i, label) in labels.iter().enumerate() {
+        let position = i + options.skip;
+        let cell = position % per_page;
+        let (row, column) = (cell / layout.columns, cell % layout.columns);
+
+        let left = layout.margin_left + column as f64 * (layout.label_width + layout.gap_x);
+        let top = layout.margin_top + row as f64 * (layout.label_height + layout.gap_y);
+        let x = mm(left) + padding;
+        let y = mm(layout.page_height - top - layout.label_height) + padding;
+        let width = mm(layout.label_width) - padding * 2.0;
+        let height = mm(layout.label_height) - padding * 2.0;
+
+        let page = document.page(position / per_page);
+
+        match options.format {
+            LabelFormat::Code128 => draw_code128(page, label, x, y, width, height)?,
+            LabelFormat::Qr => draw_qr(page, label, x, y, width, height)?,
+        }
+    }
+
+    Ok(document.to_bytes())
+}
+
+// labels come out in the order the records were selected
+async fn fetch_labels(query: &str, ids: &[i32], conn: &Pool<MySql>) -> Result<Vec<Label>, String> {
+    if ids.is_empty() {
+        return Err("Nenhum registo selecionado".to_string());
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let query = query.replace("{ids}", &placeholders);
+    let mut statement = sqlx::query_as::<_, Label>(&query);
+
+    for id in ids {
+        statement = statement.bind(id);
+    }
+
+    let mut labels = statement.fetch_all(conn).await.map_err(|e| {
+        tracing::error!("Falha ao consultar etiquetas: {}", e);
+        format!("Falha ao consultar etiquetas: {}", e)
+    })?;
+
+    labels.sort_by_key(|label| ids.iter().position(|id| *id == label.id));
+
+    Ok(labels)
+}
+
+fn write_labels(labels: &[Label], options: &LabelOptions, path: &str) -> Result<i32, String> {
+    let document = render_labels(labels, options)?;
+
+    std::fs::write(path, document).map_err(|e| {
+        tracing::error!("Falha ao guardar etiquetas: {}", e);
+        format!("Falha ao guardar etiquetas: {}", e)
+    })?;
+
+    Ok(labels.len() as i32)
+}
+
+// spine labels go on the copies, so each selected book gets one label per copy carrying its code
+#[tauri::command]
+pub async fn print_book_labels(
+    token: String,
+    book_ids: Vec<i32>,
+    options: LabelOptions,
+    path: String,
+    state: tauri::State<'_, Database>,
+) -> Result<i32, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let labels = fetch_labels(
+        "SELECT livros.id, exemplares.codigo AS code, livros.nome AS text FROM exemplares
+        INNER JOIN livros ON livros.id = exemplares.id_livro
+        WHERE livros.id IN ({ids}) AND exemplares.codigo IS NOT NULL
+        ORDER BY exemplares.id",
+        &book_ids,
+        pool,
+    )
+    .await?;
+
+    if labels.is_empty() {
+        return Err("Os livros selecionados não têm exemplares"
+            .to_string()
+            .into());
+    }
+
+    Ok(write_labels(&labels, &options, &path)?)
+}
+
+#[tauri::command]
+pub async fn print_reader_cards(
+    token: String,
+    reader_ids: Vec<i32>,
+    options: LabelOptions,
+    path: String,
+    state: tauri::State<'_, Database>,
+) -> Result<i32, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let labels = fetch_labels(
+        "SELECT id, numero_cartao AS code, nome AS text FROM leitores
+        WHERE id IN ({ids}) AND numero_cartao IS NOT NULL",
+        &reader_ids,
+        pool,
+    )
+    .await?;
+
+    if labels.is_empty() {
+        return Err("Leitor não encontrado".to_string().into());
+    }
+
+    Ok(write_labels(&labels, &options, &path)?)
+}
+
+#[tauri::command]
+pub fn get_default_label_layout() -> LabelLayout {
+    LabelLayout::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout_fits_an_a4_sheet() {
+        assert!(validate_layout(&LabelLayout::default(), 0).is_ok());
+        assert!(validate_layout(&LabelLayout::default(), 23).is_ok());
+    }
+
+    #[test]
+    fn skip_has_to_leave_a_label_on_the_first_sheet() {
+        assert_eq!(
+            validate_layout(&LabelLayout::default(), 24).unwrap_err(),
+            "A folha só tem 24 etiquetas, não é possível saltar 24"
+        );
+    }
+
+    #[test]
+    fn rejects_impossible_grids() {
+        let invalid = [
+            LabelLayout {
+                columns: 0,
+                ..LabelLayout::default()
+            },
+            LabelLayout {
+                label_height: 0.0,
+                ..LabelLayout::default()
+            },
+            LabelLayout {
+                gap_x: -1.0,
+                ..LabelLayout::default()
+            },
+            LabelLayout {
+                margin_top: f64::NAN,
+                ..LabelLayout::default()
+            },
+        ];
+
+        for layout in invalid {
+            assert_eq!(
+                validate_layout(&layout, 0).unwrap_err(),
+                "Grelha de etiquetas inválida"
+            );
+        }
+    }
+
+    #[test]
+    fn labels_have_to_fit_the_page() {
+        // 6.4 + 3 * 66.2 + 2 * 2.5 is exactly 210, 66.4 goes past the half millimetre of tolerance
+        let wider = LabelLayout {
+            label_width: 66.2,
+            ..LabelLayout::default()
+        };
+        let too_wide = LabelLayout {
+            label_width: 66.4,
+            ..LabelLayout::default()
+        };
+
+        assert!(validate_layout(&wider, 0).is_ok());
+        assert_eq!(
+            validate_layout(&too_wide, 0).unwrap_err(),
+            "As etiquetas não cabem na página"
+        );
+    }
+}
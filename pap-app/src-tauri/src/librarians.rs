@@ -1,53 +1,243 @@
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use sqlx::{MySql, Pool};
 
 use crate::{
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
     db_structs::{Bibliotecario, Permissao},
-    jwt::{get_from_jwt, new_jwt, verify_jwt},
+    errors::CommandError,
+    jwt::{
+        create_session, get_from_jwt, new_challenge_jwt, new_jwt, refresh_session,
+        revoke_librarian_sessions, revoke_other_sessions, revoke_session, verify_challenge_jwt,
+        verify_jwt, verify_session,
+    },
+    login_attempts::{
+        clear_failures, client_key, failure_delay, is_locked, register_failure, unlock_account,
+        DUMMY_HASH,
+    },
+    passwords::{
+        check_history, generate_temporary_password, hash_password, load_policy, record_password,
+        validate_password,
+    },
+    permissions::{has_permission, librarian_id, verify_permission, AuthError},
+    totp::{is_enrollment_pending, is_totp_enabled, verify_second_factor},
     Database,
 };
 
+// the password hash is left out of the audit log
+const LIBRARIAN_SNAPSHOT: &str = "SELECT id, nome, cargo FROM bibliotecarios WHERE id = ?";
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginResponse {
+    pub token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub must_change_password: bool,
+    pub must_enroll_totp: bool,
+    // set instead of the tokens when the librarian still has to send a code to login_totp
+    pub totp_challenge: Option<String>,
+}
+
+async fn start_session(
+    librarian: &Bibliotecario,
+    conn: &Pool<MySql>,
+) -> Result<LoginResponse, String> {
+    let (session_id, refresh_token) = create_session(librarian.id, conn).await?;
+    let must_enroll_totp = is_enrollment_pending(librarian, conn).await?;
+
+    Ok(LoginResponse {
+        token: Some(new_jwt(librarian, &session_id, must_enroll_totp)?),
+        refresh_token: Some(refresh_token),
+        must_change_password: librarian.alterar_password,
+        must_enroll_totp,
+        totp_challenge: None,
+    })
+}
+
 #[tauri::command]
 pub async fn login(
     name: String,
     password: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<String, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    client_id: Option<String>,
+    state: tauri::State<'_, Database>,
+) -> Result<LoginResponse, CommandError> {
+    let pool = state.pool().await?;
+
+    let client = client_key(client_id, &pool).await?;
+
+    if is_locked(&name, &client, &pool).await? {
+        tracing::warn!(
+            "Tentativa de login bloqueada para {} a partir de {}",
+            name,
+            client
+        );
+        return Err("Demasiadas tentativas falhadas, tente novamente mais tarde".into());
+    }
 
     let librarian =
         sqlx::query_as::<_, Bibliotecario>("SELECT * FROM bibliotecarios WHERE nome = ?")
-            .bind(name)
-            .fetch_all(pool)
+            .bind(&name)
+            .fetch_all(&pool)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar bibliotecário: {}", e);
                 format!("Falha ao consultar bibliotecário: {}", e)
             })?;
 
-    if librarian.is_empty() {
-        return Err("Nome ou password inválidos".into());
-    }
+    let hash = librarian
+        .first()
+        .map_or(DUMMY_HASH.as_str(), |librarian| librarian.password.as_str());
 
-    let is_correct =
-        bcrypt::verify(password, &librarian.first().unwrap().password).map_err(|e| {
-            tracing::error!("Falha ao verificar password: {}", e);
-            format!("Falha ao verificar password: {}", e)
-        })?;
+    let is_correct = bcrypt::verify(password, hash).map_err(|e| {
+        tracing::error!("Falha ao verificar password: {}", e);
+        format!("Falha ao verificar password: {}", e)
+    })?;
+
+    if librarian.is_empty() || !is_correct {
+        let failures = register_failure(&name, &client, &pool).await?;
+        tokio::time::sleep(failure_delay(failures)).await;
 
-    if !is_correct {
         tracing::warn!("Nome ou password inválidos");
         return Err("Nome ou password inválidos".into());
     }
 
+    clear_failures(&name, &client, &pool).await?;
+
     let librarian = librarian.first().unwrap();
 
-    Ok(new_jwt(&librarian))
+    if is_totp_enabled(librarian.id, &pool).await? {
+        return Ok(LoginResponse {
+            token: None,
+            refresh_token: None,
+            must_change_password: librarian.alterar_password,
+            must_enroll_totp: false,
+            totp_challenge: Some(new_challenge_jwt(librarian.id)?),
+        });
+    }
+
+    Ok(start_session(librarian, &pool).await?)
+}
+
+#[tauri::command]
+pub async fn login_totp(
+    challenge: String,
+    code: String,
+    client_id: Option<String>,
+    state: tauri::State<'_, Database>,
+) -> Result<LoginResponse, CommandError> {
+    let pool = state.pool().await?;
+
+    let id = verify_challenge_jwt(&challenge, &pool).await?;
+
+    let librarian = sqlx::query_as::<_, Bibliotecario>("SELECT * FROM bibliotecarios WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar bibliotecário: {}", e);
+            format!("Falha ao consultar bibliotecário: {}", e)
+        })?
+        .ok_or("Nenhum bibliotecário encontrado")?;
+
+    let client = client_key(client_id, &pool).await?;
+
+    // codes share the password lockout so the six digits can not be guessed one challenge at a time
+    if is_locked(&librarian.nome, &client, &pool).await? {
+        tracing::warn!(
+            "Tentativa de login bloqueada para {} a partir de {}",
+            librarian.nome,
+            client
+        );
+        return Err("Demasiadas tentativas falhadas, tente novamente mais tarde".into());
+    }
+
+    if !verify_second_factor(librarian.id, &code, &pool).await? {
+        let failures = register_failure(&librarian.nome, &client, &pool).await?;
+        tokio::time::sleep(failure_delay(failures)).await;
+
+        tracing::warn!("Código de autenticação inválido para {}", librarian.nome);
+        return Err("Código inválido".into());
+    }
+
+    clear_failures(&librarian.nome, &client, &pool).await?;
+
+    Ok(start_session(&librarian, &pool).await?)
+}
+
+#[tauri::command]
+pub async fn unlock_librarian(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_bibliotecario", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let name: String = sqlx::query_scalar("SELECT nome FROM bibliotecarios WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar: {}", e);
+            format!("Falha ao consultar: {}", e)
+        })?
+        .ok_or("Bibliotecário não existe")?;
+
+    Ok(unlock_account(&name, pool).await?)
+}
+
+#[tauri::command]
+pub async fn refresh_token(
+    refresh_token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<LoginResponse, CommandError> {
+    let pool = &state.pool().await?;
+
+    let (session, refresh_token) = refresh_session(&refresh_token, pool).await?;
+
+    // the librarian is read again so name or role changes are reflected in the new token
+    let librarian = sqlx::query_as::<_, Bibliotecario>("SELECT * FROM bibliotecarios WHERE id = ?")
+        .bind(session.id_bibliotecario)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar bibliotecário: {}", e);
+            format!("Falha ao consultar bibliotecário: {}", e)
+        })?
+        .ok_or("Nenhum bibliotecário encontrado")?;
+
+    // enrolling or being promoted is picked up on the next refresh
+    let must_enroll_totp = is_enrollment_pending(&librarian, pool).await?;
+
+    Ok(LoginResponse {
+        token: Some(new_jwt(&librarian, &session.id, must_enroll_totp)?),
+        refresh_token: Some(refresh_token),
+        must_change_password: librarian.alterar_password,
+        must_enroll_totp,
+        totp_challenge: None,
+    })
+}
+
+#[tauri::command]
+pub async fn logout(token: String, state: tauri::State<'_, Database>) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    // librarians with a pending password change or second factor enrolment can still end their session
+    verify_session(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let claims = get_from_jwt(&token).map_err(|e| {
+        tracing::error!("Falha ao obter claims: {}", e);
+        format!("Falha ao obter claims: {}", e)
+    })?;
+
+    Ok(revoke_session(claims.get("sid").ok_or("token inválido")?, pool).await?)
 }
 
 #[tauri::command]
@@ -56,35 +246,20 @@ pub async fn new_librarian(
     name: String,
     password: String,
     role: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
-
-    if token == "first_librarian" {
-        let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM bibliotecarios")
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar: {}", e);
-                format!("Falha ao consultar: {}", e)
-            })?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-        if count > 0 {
-            return Err("Já existe um bibliotecário".to_string());
-        }
-    } else {
-        verify_jwt(&token, &pool).await.map_err(|e| {
-            tracing::error!("Falha ao verificar token: {}", e);
-            format!("Falha ao verificar token: {}", e)
+    verify_permission(&token, "criar_bibliotecario", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
         })?;
-    }
 
-    let hashed_password = bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap();
+    validate_password(&password, &load_policy(pool).await?)?;
+
+    let hashed_password = hash_password(&password)?;
 
     if role.is_some() {
         let role_count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM cargos WHERE nome = ?")
@@ -97,44 +272,157 @@ pub async fn new_librarian(
             })?;
 
         if role_count == 0 {
-            return Err("Cargo não existe".to_string());
+            return Err("Cargo não existe".to_string().into());
         }
+    }
 
-        sqlx::query("INSERT INTO bibliotecarios (nome, password, cargo) VALUES (?, ?, ?)")
-            .bind(name)
-            .bind(hashed_password)
-            .bind(role)
-            .execute(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao inserir bibliotecário: {}", e);
-                format!("Falha ao inserir bibliotecário: {}", e)
-            })?;
-    } else {
-        sqlx::query("INSERT INTO bibliotecarios (nome, password) VALUES (?, ?)")
-            .bind(name)
-            .bind(hashed_password)
-            .execute(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao inserir bibliotecário: {}", e);
-                format!("Falha ao inserir bibliotecário: {}", e)
-            })?;
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    // the password was chosen by someone else, so it has to be changed on the first login
+    let result = sqlx::query(
+        "INSERT INTO bibliotecarios (nome, password, cargo, alterar_password) VALUES (?, ?, ?, TRUE)",
+    )
+    .bind(name)
+    .bind(&hashed_password)
+    .bind(role)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao inserir bibliotecário: {}", e);
+        format!("Falha ao inserir bibliotecário: {}", e)
+    })?;
+
+    let id = result.last_insert_id() as i32;
+
+    record_password(id, &hashed_password, &mut tx).await?;
+
+    let after = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        CREATE,
+        "bibliotecarios",
+        id,
+        None,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn bootstrap_admin(
+    name: String,
+    password: String,
+    role: String,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    validate_password(&password, &load_policy(pool).await?)?;
+
+    let hashed_password = hash_password(&password)?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    // the primary key makes a concurrent bootstrap fail here instead of creating a second admin
+    sqlx::query("INSERT INTO configuracoes (chave, valor) VALUES ('bootstrap_concluido', '1')")
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao iniciar configuração: {}", e);
+            match e.as_database_error() {
+                Some(db_error) if db_error.is_unique_violation() => {
+                    "O primeiro acesso já foi configurado".to_string()
+                }
+                _ => format!("Falha ao iniciar configuração: {}", e),
+            }
+        })?;
+
+    let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM bibliotecarios FOR UPDATE")
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar: {}", e);
+            format!("Falha ao consultar: {}", e)
+        })?;
+
+    if count > 0 {
+        return Err("Já existe um bibliotecário".to_string().into());
     }
 
+    sqlx::query("DELETE FROM cargos WHERE nome = ?")
+        .bind(&role)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao excluir cargo: {}", e);
+            format!("Falha ao excluir cargo: {}", e)
+        })?;
+
+    sqlx::query("INSERT INTO cargos (nome, permissao) SELECT ?, id FROM permissoes")
+        .bind(&role)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao inserir cargo: {}", e);
+            format!("Falha ao inserir cargo: {}", e)
+        })?;
+
+    let result = sqlx::query("INSERT INTO bibliotecarios (nome, password, cargo) VALUES (?, ?, ?)")
+        .bind(name)
+        .bind(&hashed_password)
+        .bind(&role)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao inserir bibliotecário: {}", e);
+            format!("Falha ao inserir bibliotecário: {}", e)
+        })?;
+
+    sqlx::query(
+        "INSERT INTO historico_passwords (id_bibliotecario, password, data_alteracao) VALUES (?, ?, NOW())",
+    )
+    .bind(result.last_insert_id())
+    .bind(&hashed_password)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao guardar histórico de passwords: {}", e);
+        format!("Falha ao guardar histórico de passwords: {}", e)
+    })?;
+
+    let id = result.last_insert_id() as i32;
+    let after = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
+    record_audit(None, CREATE, "bibliotecarios", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
 #[tauri::command]
 pub async fn check_librarians_existence(
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<bool, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<bool, CommandError> {
+    let pool = &state.pool().await?;
 
     let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM bibliotecarios")
         .fetch_one(pool)
@@ -151,35 +439,31 @@ pub async fn check_librarians_existence(
 pub async fn does_librarian_has_permission(
     token: String,
     permission_id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<bool, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<bool, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let claims = get_from_jwt(&token).map_err(|e| {
-        tracing::error!("Falha ao obter claims: {}", e);
-        format!("Falha ao obter claims: {}", e)
-    })?;
+    let id = librarian_id(&token)?;
 
-    let count: i32 =
-        sqlx::query_scalar("SELECT COUNT(*) FROM cargos WHERE nome = ? AND permissao = ?")
-            .bind(claims.get("role").unwrap())
-            .bind(permission_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar: {}", e);
-                format!("Falha ao consultar: {}", e)
-            })?;
+    // like has_permission, the role comes from the database and not from the token
+    let count: i32 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM cargos
+        INNER JOIN bibliotecarios ON bibliotecarios.cargo = cargos.nome
+        WHERE bibliotecarios.id = ? AND cargos.permissao = ?",
+    )
+    .bind(id)
+    .bind(permission_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar: {}", e);
+        format!("Falha ao consultar: {}", e)
+    })?;
 
     Ok(count > 0)
 }
@@ -188,75 +472,37 @@ pub async fn does_librarian_has_permission(
 pub async fn does_librarian_has_permission_by_acao(
     token: String,
     acao: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<bool, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
-    })?;
-
-    let claims = get_from_jwt(&token).map_err(|e| {
-        tracing::error!("Falha ao obter claims: {}", e);
-        format!("Falha ao obter claims: {}", e)
-    })?;
-
-    let permission_id: i32 = sqlx::query_scalar("SELECT id FROM permissoes WHERE acao = ?")
-        .bind(acao)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar: {}", e);
-            format!("Falha ao consultar: {}", e)
-        })?;
-
-    let count: i32 =
-        sqlx::query_scalar("SELECT COUNT(*) FROM cargos WHERE nome = ? AND permissao = ?")
-            .bind(claims.get("role").unwrap())
-            .bind(permission_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar: {}", e);
-                format!("Falha ao consultar: {}", e)
-            })?;
-
-    Ok(count > 0)
+    state: tauri::State<'_, Database>,
+) -> Result<bool, CommandError> {
+    let pool = &state.pool().await?;
+
+    Ok(has_permission(&token, &acao, pool).await.map_err(|e| {
+        tracing::error!("{}", e);
+        e
+    })?)
 }
 
 #[tauri::command]
 pub async fn get_librarian_permissions(
     token: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Permissao>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Permissao>, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let claims = get_from_jwt(&token).map_err(|e| {
-        tracing::error!("Falha ao obter claims: {}", e);
-        format!("Falha ao obter claims: {}", e)
-    })?;
+    let id = librarian_id(&token)?;
 
     let permissions: Vec<Permissao> = sqlx::query_as::<_, Permissao>(
         "SELECT permissoes.id, permissoes.acao, permissoes.label FROM permissoes
-        WHERE permissoes.id IN (SELECT permissao FROM cargos WHERE nome = ?)",
+        WHERE permissoes.id IN (SELECT cargos.permissao FROM cargos
+            INNER JOIN bibliotecarios ON bibliotecarios.cargo = cargos.nome
+            WHERE bibliotecarios.id = ?)",
     )
-    .bind(claims.get("role").unwrap())
+    .bind(id)
     .fetch_all(pool)
     .await
     .map_err(|e| {
@@ -277,18 +523,13 @@ pub struct LibrarianAsResponse {
 #[tauri::command]
 pub async fn get_librarians(
     token: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<LibrarianAsResponse>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<LibrarianAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let librarians: Vec<LibrarianAsResponse> =
@@ -307,18 +548,13 @@ pub async fn get_librarians(
 pub async fn get_librarian_by_id(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<LibrarianAsResponse, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<LibrarianAsResponse, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let librarian: LibrarianAsResponse = sqlx::query_as::<_, LibrarianAsResponse>(
@@ -339,23 +575,29 @@ pub async fn get_librarian_by_id(
 pub async fn delete_librarian(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "apagar_bibliotecario", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    let pool = &db.pool;
+    if librarian_id(&token)? == id {
+        return Err("Não pode apagar a sua própria conta".to_string().into());
+    }
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
     let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM bibliotecarios WHERE id = ?")
         .bind(id)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
@@ -363,18 +605,52 @@ pub async fn delete_librarian(
         })?;
 
     if count == 0 {
-        return Err("Bibliotecário não existe".to_string());
+        return Err("Bibliotecário não existe".to_string().into());
+    }
+
+    // the first access can only be configured once, so an install must always keep a librarian;
+    // locking every librarian keeps two deletes from removing the last two at once
+    let total: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM bibliotecarios FOR UPDATE")
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar: {}", e);
+            format!("Falha ao consultar: {}", e)
+        })?;
+
+    if total <= 1 {
+        return Err("Não é possível apagar o último bibliotecário"
+            .to_string()
+            .into());
     }
 
+    let before = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
     sqlx::query("DELETE FROM bibliotecarios WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao excluir bibliotecário: {}", e);
             format!("Falha ao excluir bibliotecário: {}", e)
         })?;
 
+    record_audit(
+        Some(&token),
+        DELETE,
+        "bibliotecarios",
+        id,
+        before,
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -385,25 +661,83 @@ pub async fn update_librarian(
     name: Option<String>,
     role: Option<String>,
     password: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_bibliotecario", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    let pool = &db.pool;
+    // the new password is checked before anything is locked, hashing takes a while
+    let hashed_password = match &password {
+        Some(password) => {
+            let policy = load_policy(pool).await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+            validate_password(password, &policy)?;
+            check_history(id, password, &policy, pool).await?;
+
+            Some(hash_password(password)?)
+        }
+        None => None,
+    };
+
+    let claims = get_from_jwt(&token)?;
+    let own_account = claims.get("id") == Some(&id.to_string());
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    if name.is_some() {
+    let before = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Bibliotecário não existe".to_string().into());
+    }
+
+    if let Some(role) = &role {
+        if own_account {
+            return Err("Não pode alterar o seu próprio cargo".to_string().into());
+        }
+
+        // a role may only be given by someone who already holds every permission in it
+        let (role_exists, grants_more): (bool, bool) = sqlx::query_as(
+            "SELECT EXISTS(SELECT * FROM cargos WHERE nome = ?),
+            EXISTS(SELECT * FROM cargos WHERE nome = ? AND permissao NOT IN (
+                SELECT cargos.permissao FROM cargos
+                INNER JOIN bibliotecarios ON bibliotecarios.cargo = cargos.nome
+                WHERE bibliotecarios.id = ?))",
+        )
+        .bind(role)
+        .bind(role)
+        .bind(librarian_id(&token)?)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar: {}", e);
+            format!("Falha ao consultar: {}", e)
+        })?;
+
+        if !role_exists {
+            return Err("Cargo não existe".to_string().into());
+        }
+
+        if grants_more {
+            return Err("Não pode atribuir um cargo com permissões que não tem"
+                .to_string()
+                .into());
+        }
+    }
+
+    if let Some(name) = &name {
         sqlx::query("UPDATE bibliotecarios SET nome = ? WHERE id = ?")
-            .bind(&name.unwrap())
+            .bind(name)
             .bind(id)
-            .execute(pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao atualizar bibliotecário: {}", e);
@@ -411,11 +745,13 @@ pub async fn update_librarian(
             })?;
     }
 
-    if role.is_some() {
+    let revoke_sessions = role.is_some() || password.is_some();
+
+    if let Some(role) = &role {
         sqlx::query("UPDATE bibliotecarios SET cargo = ? WHERE id = ?")
-            .bind(&role.unwrap())
+            .bind(role)
             .bind(id)
-            .execute(pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao atualizar bibliotecário: {}", e);
@@ -423,19 +759,184 @@ pub async fn update_librarian(
             })?;
     }
 
-    if password.is_some() {
-        let hashed_password = bcrypt::hash(&password.unwrap(), bcrypt::DEFAULT_COST).unwrap();
-
-        sqlx::query("UPDATE bibliotecarios SET password = ? WHERE id = ?")
-            .bind(&hashed_password)
+    if let Some(hashed_password) = &hashed_password {
+        sqlx::query("UPDATE bibliotecarios SET password = ?, alterar_password = ? WHERE id = ?")
+            .bind(hashed_password)
+            .bind(!own_account)
             .bind(id)
-            .execute(pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao atualizar bibliotecário: {}", e);
                 format!("Falha ao atualizar bibliotecário: {}", e)
             })?;
+
+        record_password(id, hashed_password, &mut tx).await?;
+    }
+
+    if revoke_sessions {
+        revoke_librarian_sessions(id, &mut tx).await?;
     }
 
+    let after = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "bibliotecarios",
+        id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+#[tauri::command]
+pub async fn change_password(
+    token: String,
+    current_password: String,
+    new_password: String,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    // a pending password change must still be allowed through here
+    let librarian = verify_session(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let is_correct = bcrypt::verify(current_password, &librarian.password).map_err(|e| {
+        tracing::error!("Falha ao verificar password: {}", e);
+        format!("Falha ao verificar password: {}", e)
+    })?;
+
+    if !is_correct {
+        return Err("Password atual inválida".to_string().into());
+    }
+
+    let policy = load_policy(pool).await?;
+
+    validate_password(&new_password, &policy)?;
+    check_history(librarian.id, &new_password, &policy, pool).await?;
+
+    let hashed_password = hash_password(&new_password)?;
+
+    let claims = get_from_jwt(&token)?;
+    let session_id = claims.get("sid").ok_or("token inválido")?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, librarian.id, &mut tx).await?;
+
+    sqlx::query("UPDATE bibliotecarios SET password = ?, alterar_password = FALSE WHERE id = ?")
+        .bind(&hashed_password)
+        .bind(librarian.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao atualizar password: {}", e);
+            format!("Falha ao atualizar password: {}", e)
+        })?;
+
+    record_password(librarian.id, &hashed_password, &mut tx).await?;
+
+    let after = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, librarian.id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "bibliotecarios",
+        librarian.id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    revoke_other_sessions(librarian.id, session_id, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn reset_librarian_password(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<String, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_bibliotecario", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let policy = load_policy(pool).await?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
+    let temporary_password = generate_temporary_password(&policy);
+    let hashed_password = hash_password(&temporary_password)?;
+
+    let result =
+        sqlx::query("UPDATE bibliotecarios SET password = ?, alterar_password = TRUE WHERE id = ?")
+            .bind(&hashed_password)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao atualizar password: {}", e);
+                format!("Falha ao atualizar password: {}", e)
+            })?;
+
+    if result.rows_affected() == 0 {
+        return Err("Bibliotecário não existe".to_string().into());
+    }
+
+    record_password(id, &hashed_password, &mut tx).await?;
+    revoke_librarian_sessions(id, &mut tx).await?;
+
+    let after = snapshot::<LibrarianAsResponse>(LIBRARIAN_SNAPSHOT, id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "bibliotecarios",
+        id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(temporary_password)
+}
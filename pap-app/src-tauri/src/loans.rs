@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::{
+    errors::CommandError,
+    jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
+    settings::{get_setting, set_setting},
+    Database,
+};
+
+const DEFAULT_LOAN_DAYS: i32 = 7;
+const DEFAULT_MAX_RENEWALS: i32 = 2;
+const DEFAULT_HOLD_PICKUP_DAYS: i32 = 3;
+
+#[derive(Serialize, Deserialize)]
+pub struct LoanPolicy {
+    pub loan_days: i32,
+    pub max_renewals: i32,
+    pub hold_pickup_days: i32,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct CategoryLoanPeriod {
+    pub id_categoria: i32,
+    pub categoria: String,
+    pub dias: i32,
+}
+
+pub async fn load_loan_policy(conn: &Pool<MySql>) -> Result<LoanPolicy, String> {
+    Ok(LoanPolicy {
+        loan_days: get_setting("emprestimo_dias", DEFAULT_LOAN_DAYS, conn).await?,
+        max_renewals: get_setting("emprestimo_renovacoes", DEFAULT_MAX_RENEWALS, conn).await?,
+        hold_pickup_days: get_setting("reserva_dias_levantamento", DEFAULT_HOLD_PICKUP_DAYS, conn)
+            .await?,
+    })
+}
+
+// the book's category wins over the reader's type, which wins over the library default
+pub async fn loan_period_days(
+    book_id: i32,
+    reader_id: i32,
+    conn: &Pool<MySql>,
+) -> Result<i32, String> {
+    let days: Option<i32> = sqlx::query_scalar(
+        "SELECT COALESCE(
+            (SELECT prazos_categoria.dias FROM livros
+            INNER JOIN sub_categorias ON sub_categorias.id = livros.id_sub_categoria
+            INNER JOIN prazos_categoria ON prazos_categoria.id_categoria = sub_categorias.id_categoria
+            WHERE livros.id = ?),
+            (SELECT tipos_leitor.dias_emprestimo FROM leitores
+            INNER JOIN tipos_leitor ON tipos_leitor.id = leitores.id_tipo
+            WHERE leitores.id = ?)
+        )",
+    )
+    .bind(book_id)
+    .bind(reader_id)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar prazo de empréstimo: {}", e);
+        format!("Falha ao consultar prazo de empréstimo: {}", e)
+    })?;
+
+    match days {
+        Some(days) => Ok(days),
+        None => Ok(load_loan_policy(conn).await?.loan_days),
+    }
+}
+
+pub async fn renewal_limit(reader_id: i32, conn: &Pool<MySql>) -> Result<i32, String> {
+    let max_renewals: Option<i32> = sqlx::query_scalar(
+        "SELECT tipos_leitor.max_renovacoes FROM leitores
+        INNER JOIN tipos_leitor ON tipos_leitor.id = leitores.id_tipo
+        WHERE leitores.id = ?",
+    )
+    .bind(reader_id)
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar limite de renovações: {}", e);
+        format!("Falha ao consultar limite de renovações: {}", e)
+    })?
+    .flatten();
+
+    match max_renewals {
+        Some(max) => Ok(max),
+        None => Ok(load_loan_policy(conn).await?.max_renewals),
+    }
+}
+
+#[tauri::command]
+pub async fn get_loan_policy(
+    token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<LoanPolicy, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    Ok(load_loan_policy(pool).await?)
+}
+
+#[tauri::command]
+pub async fn update_loan_policy(
+    token: String,
+    policy: LoanPolicy,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    if policy.loan_days < 1 || policy.max_renewals < 0 || policy.hold_pickup_days < 1 {
+        return Err("Política de empréstimos inválida".to_string().into());
+    }
+
+    set_setting("emprestimo_dias", policy.loan_days, pool).await?;
+    set_setting("emprestimo_renovacoes", policy.max_renewals, pool).await?;
+    set_setting("reserva_dias_levantamento", policy.hold_pickup_days, pool).await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_category_loan_periods(
+    token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<CategoryLoanPeriod>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let periods = sqlx::query_as::<_, CategoryLoanPeriod>(
+        "SELECT prazos_categoria.id_categoria, categorias.nome AS categoria, prazos_categoria.dias FROM prazos_categoria
+        INNER JOIN categorias ON categorias.id = prazos_categoria.id_categoria
+        ORDER BY categorias.nome",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar prazos de empréstimo: {}", e);
+        format!("Falha ao consultar prazos de empréstimo: {}", e)
+    })?;
+
+    Ok(periods)
+}
+
+// days as None removes the override so the category falls back to the default period
+#[tauri::command]
+pub async fn set_category_loan_period(
+    token: String,
+    category_id: i32,
+    days: Option<i32>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    match days {
+        Some(days) if days < 1 => Err("Prazo de empréstimo inválido".into()),
+        Some(days) => {
+            sqlx::query(
+                "INSERT INTO prazos_categoria (id_categoria, dias) VALUES (?, ?) ON DUPLICATE KEY UPDATE dias = VALUES(dias)",
+            )
+            .bind(category_id)
+            .bind(days)
+            .execute(pool)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao guardar prazo de empréstimo: {}", e);
+                format!("Falha ao guardar prazo de empréstimo: {}", e)
+            })?;
+
+            Ok(())
+        }
+        None => {
+            sqlx::query("DELETE FROM prazos_categoria WHERE id_categoria = ?")
+                .bind(category_id)
+                .execute(pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Falha ao apagar prazo de empréstimo: {}", e);
+                    format!("Falha ao apagar prazo de empréstimo: {}", e)
+                })?;
+
+            Ok(())
+        }
+    }
+}
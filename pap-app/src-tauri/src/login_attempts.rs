@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use sqlx::{MySql, Pool};
+
+const ACCOUNT_MAX_FAILED_ATTEMPTS: i32 = 5;
+const CLIENT_MAX_FAILED_ATTEMPTS: i32 = 20;
+const LOCKOUT_MINUTES: i64 = 15;
+const MAX_DELAY_MILLIS: u64 = 8000;
+
+lazy_static::lazy_static! {
+    // verified against when the name does not exist so unknown users take as long as wrong passwords
+    pub static ref DUMMY_HASH: String = bcrypt::hash("libra-hub", bcrypt::DEFAULT_COST).unwrap();
+}
+
+// each install sends an id generated on its first run, so machines behind the same host or NAT
+// do not share one lockout; older frontends without it fall back to the connection host
+pub async fn client_key(install_id: Option<String>, conn: &Pool<MySql>) -> Result<String, String> {
+    if let Some(id) = install_id
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+    {
+        if id.len() > 64 {
+            tracing::error!("Identificador de cliente inválido");
+            return Err("Identificador de cliente inválido".to_string());
+        }
+
+        return Ok(format!("instalacao:{}", id));
+    }
+
+    let host: String = sqlx::query_scalar("SELECT SUBSTRING_INDEX(USER(), '@', -1)")
+        .fetch_one(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar cliente: {}", e);
+            format!("Falha ao consultar cliente: {}", e)
+        })?;
+
+    Ok(host)
+}
+
+pub async fn is_locked(name: &str, client: &str, conn: &Pool<MySql>) -> Result<bool, String> {
+    let locked: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT * FROM tentativas_login WHERE ((tipo = 'conta' AND chave = ?) OR (tipo = 'cliente' AND chave = ?)) AND bloqueado_ate > NOW())",
+    )
+    .bind(name)
+    .bind(client)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar tentativas: {}", e);
+        format!("Falha ao consultar tentativas: {}", e)
+    })?;
+
+    Ok(locked)
+}
+
+async fn increment(
+    kind: &str,
+    key: &str,
+    max_attempts: i32,
+    conn: &Pool<MySql>,
+) -> Result<i32, String> {
+    // failures older than the lockout window are forgotten instead of accumulating forever
+    sqlx::query(
+        "INSERT INTO tentativas_login (tipo, chave, falhas, ultima_falha) VALUES (?, ?, 1, NOW())
+        ON DUPLICATE KEY UPDATE
+            falhas = IF(ultima_falha < DATE_SUB(NOW(), INTERVAL ? MINUTE), 1, falhas + 1),
+            bloqueado_ate = IF(falhas >= ?, DATE_ADD(NOW(), INTERVAL ? MINUTE), bloqueado_ate),
+            ultima_falha = NOW()",
+    )
+    .bind(kind)
+    .bind(key)
+    .bind(LOCKOUT_MINUTES)
+    .bind(max_attempts)
+    .bind(LOCKOUT_MINUTES)
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao registar tentativa: {}", e);
+        format!("Falha ao registar tentativa: {}", e)
+    })?;
+
+    let failures: i32 =
+        sqlx::query_scalar("SELECT falhas FROM tentativas_login WHERE tipo = ? AND chave = ?")
+            .bind(kind)
+            .bind(key)
+            .fetch_one(conn)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar tentativas: {}", e);
+                format!("Falha ao consultar tentativas: {}", e)
+            })?;
+
+    Ok(failures)
+}
+
+pub async fn register_failure(name: &str, client: &str, conn: &Pool<MySql>) -> Result<i32, String> {
+    increment("cliente", client, CLIENT_MAX_FAILED_ATTEMPTS, conn).await?;
+
+    increment("conta", name, ACCOUNT_MAX_FAILED_ATTEMPTS, conn).await
+}
+
+pub async fn clear_failures(name: &str, client: &str, conn: &Pool<MySql>) -> Result<(), String> {
+    sqlx::query(
+        "DELETE FROM tentativas_login WHERE (tipo = 'conta' AND chave = ?) OR (tipo = 'cliente' AND chave = ?)",
+    )
+    .bind(name)
+    .bind(client)
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao limpar tentativas: {}", e);
+        format!("Falha ao limpar tentativas: {}", e)
+    })?;
+
+    Ok(())
+}
+
+pub async fn unlock_account(name: &str, conn: &Pool<MySql>) -> Result<(), String> {
+    sqlx::query("DELETE FROM tentativas_login WHERE tipo = 'conta' AND chave = ?")
+        .bind(name)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao desbloquear conta: {}", e);
+            format!("Falha ao desbloquear conta: {}", e)
+        })?;
+
+    Ok(())
+}
+
+pub fn failure_delay(failures: i32) -> Duration {
+    let exponent = failures.clamp(1, 16) as u32 - 1;
+
+    Duration::from_millis((250u64 << exponent).min(MAX_DELAY_MILLIS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_delay_doubles_up_to_the_maximum() {
+        assert_eq!(failure_delay(0), Duration::from_millis(250));
+        assert_eq!(failure_delay(1), Duration::from_millis(250));
+        assert_eq!(failure_delay(2), Duration::from_millis(500));
+        assert_eq!(failure_delay(5), Duration::from_millis(4000));
+        assert_eq!(failure_delay(6), Duration::from_millis(MAX_DELAY_MILLIS));
+        assert_eq!(
+            failure_delay(i32::MAX),
+            Duration::from_millis(MAX_DELAY_MILLIS)
+        );
+    }
+}
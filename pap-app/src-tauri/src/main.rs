@@ -1,77 +1,156 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod audit;
 mod authors;
+mod barcodes;
+mod book_rows;
 mod books;
 mod categories;
+mod copies;
 mod db_structs;
+mod errors;
+mod fines;
+mod holds;
+mod isbn;
 mod jwt;
+mod labels;
 mod librarians;
+mod loans;
+mod login_attempts;
+mod migrations;
+mod passwords;
+mod pdf;
 mod permissions;
 mod publishers;
+mod qrcode;
+mod reader_types;
 mod readers;
 mod requests;
 mod roles;
+mod settings;
 mod sub_categories;
-mod tables;
+mod totp;
 
+use audit::get_audit_log;
 use authors::{
     create_author, delete_author, get_author_by_id, get_authors, get_authors_by_name,
     get_authors_count, get_books_by_author_id, update_author,
 };
-use books::{create_book, delete_book, get_book_by_id, get_books, get_books_count, update_book};
+use books::{
+    create_book, delete_book, get_book_by_id, get_book_by_isbn, get_books, get_books_count,
+    update_book, validate_isbn,
+};
 use categories::{create_category, delete_category, get_categories};
+use copies::{create_copy, delete_copy, get_copies_by_book_id, update_copy};
+use fines::{
+    get_fine_policy, get_reader_balance, get_reader_ledger, record_payment, update_fine_policy,
+    waive_fine,
+};
+use holds::{cancel_hold, get_holds_by_book_id, get_holds_by_reader_id, place_hold};
+use jwt::{load_keys, rotate_jwt_key};
+use labels::{get_default_label_layout, print_book_labels, print_reader_cards};
 use librarians::{
-    check_librarians_existence, delete_librarian, does_librarian_has_permission,
-    does_librarian_has_permission_by_acao, get_librarian_by_id, get_librarian_permissions,
-    get_librarians, login, new_librarian, update_librarian,
+    bootstrap_admin, change_password, check_librarians_existence, delete_librarian,
+    does_librarian_has_permission, does_librarian_has_permission_by_acao, get_librarian_by_id,
+    get_librarian_permissions, get_librarians, login, login_totp, logout, new_librarian,
+    refresh_token, reset_librarian_password, unlock_librarian, update_librarian,
 };
+use loans::{
+    get_category_loan_periods, get_loan_policy, set_category_loan_period, update_loan_policy,
+};
+use migrations::{get_pending_migrations, revert_migrations, run_migrations};
+use passwords::{get_password_policy, update_password_policy};
 use permissions::{add_permission_to_role, get_permissions};
 use publishers::{
     create_publisher, delete_publisher, get_books_by_publisher_id, get_publisher_by_id,
     get_publishers, get_publishers_by_name, get_publishers_count, update_publisher,
 };
+use reader_types::{create_reader_type, delete_reader_type, get_reader_types, update_reader_type};
 use readers::{
-    create_reader, delete_reader, get_reader_by_id, get_readers, get_readers_by_name,
-    get_readers_count, get_requested_books_by_reader_id, update_reader,
+    create_reader, delete_reader, get_reader_by_card_number, get_reader_by_id, get_readers,
+    get_readers_by_name, get_readers_count, get_requested_books_by_reader_id,
+    set_reader_card_number, update_reader,
+};
+use requests::{
+    checkout_by_barcode, get_loan_history, get_overdue_loans, get_reader_loan_history,
+    get_requested_book_by_book_id, get_requests, renew_loan, request_book, return_book,
+    return_by_barcode,
 };
-use requests::{get_requested_book_by_book_id, request_book, return_book, get_requests};
 use roles::{create_role, delete_role, get_role_by_name, get_roles, update_role};
 use sqlx::{mysql::MySqlPoolOptions, MySql, Pool};
 use sub_categories::{
     create_sub_category, delete_sub_category, get_sub_categories, get_sub_categories_by_name,
 };
-use tables::create_tables;
 use tauri::Manager;
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
+use totp::{
+    confirm_totp, disable_totp, enroll_totp, get_totp_status, reset_librarian_totp,
+    set_totp_required,
+};
 use tracing_subscriber::FmtSubscriber;
 
+const DEFAULT_POOL_SIZE: u32 = 5;
+const MAX_POOL_SIZE: u32 = 100;
+
+// commands copy the pool handle out of the lock, so they run concurrently and only wait while init swaps it
+#[derive(Default)]
 pub struct Database {
-    pub pool: Pool<MySql>,
+    pool: RwLock<Option<Pool<MySql>>>,
 }
 
-lazy_static::lazy_static! {
-    static ref SECRET: String = chrono::Utc::now().timestamp().to_string();
+impl Database {
+    pub async fn pool(&self) -> Result<Pool<MySql>, String> {
+        self.pool
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| "Base de dados não inicializada".to_string())
+    }
+
+    // the previous pool closes in the background once the queries it is running finish, so neither this
+    // command nor the ones waiting on the new pool wait for a slow query on the old server
+    async fn set_pool(&self, pool: Pool<MySql>) {
+        let previous = self.pool.write().await.replace(pool);
+
+        if let Some(previous) = previous {
+            tokio::spawn(async move { previous.close().await });
+        }
+    }
+}
+
+async fn connect(url: &str, pool_size: u32) -> Result<Pool<MySql>, String> {
+    MySqlPoolOptions::new()
+        .max_connections(pool_size)
+        .connect(url)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao criar pool: {}", e);
+            format!("Falha ao criar pool: {}", e)
+        })
 }
 
 #[tauri::command]
 async fn init(
     db_url: String,
     make_tables: Option<bool>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
+    pool_size: Option<u32>,
+    state: tauri::State<'_, Database>,
 ) -> Result<(), String> {
+    let pool_size = pool_size.unwrap_or(DEFAULT_POOL_SIZE);
+
+    if pool_size == 0 || pool_size > MAX_POOL_SIZE {
+        return Err(format!(
+            "O número de conexões tem de estar entre 1 e {}",
+            MAX_POOL_SIZE
+        ));
+    }
+
     if make_tables.unwrap_or(false) {
-        let db_name = db_url.split('/').last().unwrap();
+        let db_name = db_url.split('/').next_back().unwrap();
         let no_name_db_url = db_url.replace(db_name, "");
 
-        let no_db_pool = MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect(no_name_db_url.as_ref())
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao criar pool: {}", e);
-                format!("Falha ao criar pool: {}", e)
-            })?;
+        let no_db_pool = connect(&no_name_db_url, pool_size).await?;
 
         let query = format!("CREATE DATABASE IF NOT EXISTS `{}`", db_name);
 
@@ -83,32 +162,19 @@ async fn init(
                 format!("Falha ao criar base de dados: {}", e)
             })?;
 
-        let pool = MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect(db_url.as_ref())
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao criar pool: {}", e);
-                format!("Falha ao criar pool: {}", e)
-            })?;
+        no_db_pool.close().await;
+    }
 
-        *state.lock().await = Some(Database { pool: pool.clone() });
+    let pool = connect(&db_url, pool_size).await?;
 
-        create_tables(&pool)
-            .await
-            .map_err(|e| format!("Falha ao criar tabelas: {}", e))?;
-    } else {
-        let pool = MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect(db_url.as_ref())
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao criar pool: {}", e);
-                format!("Falha ao criar pool: {}", e)
-            })?;
+    // an empty database gets every table and older ones are brought up to the current schema
+    run_migrations(&pool)
+        .await
+        .map_err(|e| format!("Falha ao atualizar base de dados: {}", e))?;
 
-        *state.lock().await = Some(Database { pool });
-    }
+    load_keys(&pool).await?;
+
+    state.set_pool(pool).await;
 
     tracing::debug!("Pool criado com sucesso");
 
@@ -131,24 +197,70 @@ fn main() {
             }
             Ok(())
         })
-        .manage(Mutex::new(None::<Database>))
+        .manage(Database::default())
         .invoke_handler(tauri::generate_handler![
             // others
             init,
+            get_pending_migrations,
+            revert_migrations,
+            rotate_jwt_key,
+            get_audit_log,
             // books
             get_books,
             get_books_count,
             get_book_by_id,
+            get_book_by_isbn,
+            validate_isbn,
             create_book,
             update_book,
             delete_book,
+            // copies
+            get_copies_by_book_id,
+            create_copy,
+            update_copy,
+            delete_copy,
             // requests
             request_book,
             get_requested_book_by_book_id,
             return_book,
             get_requests,
+            get_reader_loan_history,
+            get_overdue_loans,
+            renew_loan,
+            get_loan_history,
+            checkout_by_barcode,
+            return_by_barcode,
+            // labels
+            get_default_label_layout,
+            print_book_labels,
+            print_reader_cards,
+            // reader types
+            get_reader_types,
+            create_reader_type,
+            update_reader_type,
+            delete_reader_type,
+            // fines
+            get_fine_policy,
+            update_fine_policy,
+            get_reader_balance,
+            get_reader_ledger,
+            record_payment,
+            waive_fine,
+            // holds
+            place_hold,
+            cancel_hold,
+            get_holds_by_book_id,
+            get_holds_by_reader_id,
+            // loan policy
+            get_loan_policy,
+            update_loan_policy,
+            get_category_loan_periods,
+            set_category_loan_period,
             // librarians
             login,
+            refresh_token,
+            bootstrap_admin,
+            logout,
             new_librarian,
             check_librarians_existence,
             get_librarian_permissions,
@@ -157,6 +269,19 @@ fn main() {
             get_librarian_by_id,
             update_librarian,
             delete_librarian,
+            unlock_librarian,
+            change_password,
+            reset_librarian_password,
+            get_password_policy,
+            update_password_policy,
+            login_totp,
+            // two-factor authentication
+            get_totp_status,
+            enroll_totp,
+            confirm_totp,
+            disable_totp,
+            reset_librarian_totp,
+            set_totp_required,
             // permissions
             does_librarian_has_permission,
             get_permissions,
@@ -170,6 +295,8 @@ fn main() {
             update_reader,
             delete_reader,
             get_requested_books_by_reader_id,
+            get_reader_by_card_number,
+            set_reader_card_number,
             // authors
             get_author_by_id,
             get_authors_by_name,
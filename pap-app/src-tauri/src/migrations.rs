@@ -0,0 +1,809 @@
+use serde::Serialize;
+use sqlx::{pool::PoolConnection, MySql, MySqlConnection, Pool};
+
+use crate::{connect, errors::CommandError, permissions::verify_permission, Database};
+
+// only one app at a time may change the schema, the others wait for it to finish
+const LOCK_NAME: &str = "libra_hub_migracoes";
+const LOCK_TIMEOUT_SECONDS: i32 = 60;
+
+const VERSION_TABLE: &str = "CREATE TABLE IF NOT EXISTS `versao_esquema` (
+  `versao` int(11) NOT NULL,
+  `nome` varchar(255) NOT NULL,
+  `data_aplicacao` datetime NOT NULL,
+  PRIMARY KEY (`versao`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;";
+
+struct Migration {
+    version: i32,
+    name: &'static str,
+    up: &'static [&'static str],
+    down: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+pub struct PendingMigration {
+    pub versao: i32,
+    pub nome: &'static str,
+}
+
+// the tables of the shipped dumps, books still have a single author there
+const BASELINE_UP: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS `permissoes` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `acao` varchar(255) NOT NULL,
+  `label` varchar(255) DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "INSERT INTO `permissoes` (id, acao, label) VALUES
+(1,'criar_livro','Criar Livro'),
+(2,'atualizar_livro','Atualizar Livro'),
+(3,'apagar_livro','Apagar Livro'),
+(4,'criar_bibliotecario','Criar Bibliotecário'),
+(5,'atualizar_bibliotecario','Atualizar Bibliotecário'),
+(6,'apagar_bibliotecario','Apagar Bibliotecário'),
+(7,'criar_leitor','Criar Leitor'),
+(8,'atualizar_leitor','Atualizar Leitor'),
+(9,'apagar_leitor','Apagar Leitor'),
+(10,'criar_autor','Criar Autor'),
+(11,'atualizar_autor','Atualizar Autor'),
+(12,'apagar_autor','Apagar Autor'),
+(13,'criar_cargo','Criar Cargo'),
+(14,'atualizar_cargo','Atualizar Cargo'),
+(15,'apagar_cargo','Apagar Cargo'),
+(16,'criar_categoria','Criar Categoria'),
+(17,'atualizar_categoria','Atualizar Categoria'),
+(18,'apagar_categoria','Apagar Categoria'),
+(19,'criar_requisicao','Criar Requisição'),
+(20,'atualizar_requisicao','Atualizar Requisição'),
+(21,'apagar_requisicao','Apagar Requisição'),
+(22,'criar_sub_categoria','Criar Subcategoria'),
+(23,'atualizar_sub_categoria','Atualizar Subcategoria'),
+(24,'apagar_sub_categoria','Apagar Subcategoria'),
+(25,'mudar_configuracoes','Mudar Configurações'),
+(26,'criar_editora', 'Criar Editora'),
+(27,'atualizar_editora', 'Atualizar Editora'),
+(28,'apagar_editora', 'Apagar Editora')
+ON DUPLICATE KEY UPDATE id=VALUES(id);",
+    "CREATE TABLE IF NOT EXISTS `autores` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  `nacionalidade` varchar(255) DEFAULT NULL,
+  `data_nasc` varchar(255) DEFAULT NULL,
+  `data_morte` varchar(255) DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `cargos` (
+  `nome` varchar(255) NOT NULL,
+  `permissao` int(11) NOT NULL,
+  KEY `cargos_permissoes_FK` (`permissao`),
+  CONSTRAINT `cargos_permissoes_FK` FOREIGN KEY (`permissao`) REFERENCES `permissoes` (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `bibliotecarios` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  `password` varchar(255) NOT NULL,
+  `cargo` varchar(255) DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `categorias` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `sub_categorias` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_categoria` int(11) NOT NULL,
+  `nome` varchar(255) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `FK_sub_categoria_categorias` (`id_categoria`),
+  CONSTRAINT `FK_sub_categoria_categorias` FOREIGN KEY (`id_categoria`) REFERENCES `categorias` (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `editoras` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NOT NULL,
+  `morada` varchar(255) DEFAULT NULL,
+  `codigo_postal` varchar(255) DEFAULT NULL,
+  `telefone` varchar(255) DEFAULT NULL,
+  `email` varchar(255) DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `leitores` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  `morada` varchar(255) NOT NULL,
+  `telefone` varchar(255) NOT NULL,
+  `email` varchar(255) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `livros` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(255) NOT NULL,
+  `resumo` longtext DEFAULT NULL,
+  `n_paginas` int(11) NOT NULL,
+  `idioma` varchar(255) NOT NULL,
+  `img_url` text DEFAULT NULL,
+  `ano_edicao` varchar(255) DEFAULT NULL,
+  `id_autor` int(11) DEFAULT NULL,
+  `id_editora` int(11) NOT NULL,
+  `id_sub_categoria` int(11) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `livros_id_autor` (`id_autor`),
+  KEY `livros_id_editora` (`id_editora`),
+  KEY `livros_ibfk_5` (`id_sub_categoria`),
+  CONSTRAINT `livros_ibfk_3` FOREIGN KEY (`id_autor`) REFERENCES `autores` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `livros_ibfk_4` FOREIGN KEY (`id_editora`) REFERENCES `editoras` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `livros_ibfk_5` FOREIGN KEY (`id_sub_categoria`) REFERENCES `sub_categorias` (`id`)
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+    "CREATE TABLE IF NOT EXISTS `requisicoes` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_leitor` int(11) NOT NULL,
+  `id_livro_requisitado` int(11) NOT NULL,
+  `data_requisicao` datetime NOT NULL,
+  `data_entrega` datetime DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `requisicoes_id_leitor` (`id_leitor`),
+  KEY `requisicoes_id_livro_requisitado` (`id_livro_requisitado`),
+  CONSTRAINT `requisicoes_ibfk_1` FOREIGN KEY (`id_leitor`) REFERENCES `leitores` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `requisicoes_ibfk_2` FOREIGN KEY (`id_livro_requisitado`) REFERENCES `livros` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+];
+
+const BASELINE_DOWN: &[&str] = &[
+    "DROP TABLE IF EXISTS `requisicoes`",
+    "DROP TABLE IF EXISTS `livros`",
+    "DROP TABLE IF EXISTS `leitores`",
+    "DROP TABLE IF EXISTS `editoras`",
+    "DROP TABLE IF EXISTS `sub_categorias`",
+    "DROP TABLE IF EXISTS `categorias`",
+    "DROP TABLE IF EXISTS `bibliotecarios`",
+    "DROP TABLE IF EXISTS `cargos`",
+    "DROP TABLE IF EXISTS `autores`",
+    "DROP TABLE IF EXISTS `permissoes`",
+];
+
+// versions are never renumbered, changes to the schema go in a new migration at the end
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "esquema_inicial",
+        up: BASELINE_UP,
+        down: BASELINE_DOWN,
+    },
+    Migration {
+        version: 2,
+        name: "chaves_jwt",
+        up: &["CREATE TABLE IF NOT EXISTS `chaves_jwt` (
+  `id` varchar(64) NOT NULL,
+  `segredo` varchar(255) NOT NULL,
+  `ativa` tinyint(1) NOT NULL DEFAULT 1,
+  `data_criacao` datetime NOT NULL,
+  `data_expiracao` datetime DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `chaves_jwt`"],
+    },
+    Migration {
+        version: 3,
+        name: "sessoes",
+        up: &["CREATE TABLE IF NOT EXISTS `sessoes` (
+  `id` varchar(64) NOT NULL,
+  `id_bibliotecario` int(11) NOT NULL,
+  `refresh_token` varchar(255) NOT NULL,
+  `data_criacao` datetime NOT NULL,
+  `data_expiracao` datetime NOT NULL,
+  `revogada` tinyint(1) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`id`),
+  KEY `sessoes_id_bibliotecario` (`id_bibliotecario`),
+  CONSTRAINT `sessoes_ibfk_1` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `sessoes`"],
+    },
+    Migration {
+        version: 4,
+        name: "configuracoes",
+        up: &["CREATE TABLE IF NOT EXISTS `configuracoes` (
+  `chave` varchar(255) NOT NULL,
+  `valor` varchar(255) NOT NULL,
+  PRIMARY KEY (`chave`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `configuracoes`"],
+    },
+    Migration {
+        version: 5,
+        name: "tentativas_login",
+        up: &["CREATE TABLE IF NOT EXISTS `tentativas_login` (
+  `tipo` varchar(16) NOT NULL,
+  `chave` varchar(255) NOT NULL,
+  `falhas` int(11) NOT NULL DEFAULT 0,
+  `ultima_falha` datetime NOT NULL,
+  `bloqueado_ate` datetime DEFAULT NULL,
+  PRIMARY KEY (`tipo`, `chave`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `tentativas_login`"],
+    },
+    Migration {
+        version: 6,
+        name: "bibliotecarios_alterar_password",
+        up: &["ALTER TABLE `bibliotecarios` ADD COLUMN IF NOT EXISTS `alterar_password` tinyint(1) NOT NULL DEFAULT 0;"],
+        down: &["ALTER TABLE `bibliotecarios` DROP COLUMN IF EXISTS `alterar_password`"],
+    },
+    Migration {
+        version: 7,
+        name: "historico_passwords",
+        up: &["CREATE TABLE IF NOT EXISTS `historico_passwords` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_bibliotecario` int(11) NOT NULL,
+  `password` varchar(255) NOT NULL,
+  `data_alteracao` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `historico_passwords_id_bibliotecario` (`id_bibliotecario`),
+  CONSTRAINT `historico_passwords_ibfk_1` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `historico_passwords`"],
+    },
+    Migration {
+        version: 8,
+        name: "dois_fatores",
+        up: &["CREATE TABLE IF NOT EXISTS `dois_fatores` (
+  `id_bibliotecario` int(11) NOT NULL,
+  `segredo` varchar(64) NOT NULL,
+  `ativo` tinyint(1) NOT NULL DEFAULT 0,
+  `ultimo_passo` bigint(20) DEFAULT NULL,
+  PRIMARY KEY (`id_bibliotecario`),
+  CONSTRAINT `dois_fatores_ibfk_1` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `dois_fatores`"],
+    },
+    Migration {
+        version: 9,
+        name: "codigos_recuperacao",
+        up: &["CREATE TABLE IF NOT EXISTS `codigos_recuperacao` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_bibliotecario` int(11) NOT NULL,
+  `codigo` varchar(64) NOT NULL,
+  `usado` tinyint(1) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`id`),
+  KEY `codigos_recuperacao_id_bibliotecario` (`id_bibliotecario`),
+  CONSTRAINT `codigos_recuperacao_ibfk_1` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `codigos_recuperacao`"],
+    },
+    Migration {
+        version: 10,
+        name: "auditoria",
+        up: &[
+            "CREATE TABLE IF NOT EXISTS `auditoria` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_bibliotecario` int(11) DEFAULT NULL,
+  `acao` varchar(16) NOT NULL,
+  `entidade` varchar(64) NOT NULL,
+  `id_entidade` varchar(255) NOT NULL,
+  `antes` longtext DEFAULT NULL,
+  `depois` longtext DEFAULT NULL,
+  `data` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `auditoria_entidade` (`entidade`, `id_entidade`),
+  KEY `auditoria_data` (`data`),
+  KEY `auditoria_id_bibliotecario` (`id_bibliotecario`),
+  CONSTRAINT `auditoria_ibfk_1` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE SET NULL ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+            "INSERT INTO `permissoes` (id, acao, label) VALUES
+(29,'ver_auditoria','Ver Auditoria')
+ON DUPLICATE KEY UPDATE id=VALUES(id);",
+            // roles that can change the settings get it too, so upgraded databases have someone who can use it
+            "INSERT INTO `cargos` (nome, permissao) SELECT DISTINCT nome, 29 FROM `cargos` WHERE permissao = 25
+  AND NOT EXISTS (SELECT * FROM `cargos` c WHERE c.nome = cargos.nome AND c.permissao = 29);",
+        ],
+        down: &[
+            "DELETE FROM `cargos` WHERE `permissao` = 29",
+            "DELETE FROM `permissoes` WHERE `id` = 29",
+            "DROP TABLE IF EXISTS `auditoria`",
+        ],
+    },
+    Migration {
+        version: 11,
+        name: "exemplares",
+        up: &[
+            "CREATE TABLE IF NOT EXISTS `exemplares` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_livro` int(11) NOT NULL,
+  `codigo` varchar(64) DEFAULT NULL,
+  `localizacao` varchar(255) DEFAULT NULL,
+  `estado` varchar(32) NOT NULL DEFAULT 'bom',
+  `data_aquisicao` date DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `exemplares_codigo` (`codigo`),
+  KEY `exemplares_id_livro` (`id_livro`),
+  CONSTRAINT `exemplares_ibfk_1` FOREIGN KEY (`id_livro`) REFERENCES `livros` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+            "ALTER TABLE `requisicoes`
+  ADD COLUMN IF NOT EXISTS `id_exemplar` int(11) DEFAULT NULL AFTER `id_livro_requisitado`,
+  ADD KEY IF NOT EXISTS `requisicoes_id_exemplar` (`id_exemplar`),
+  ADD CONSTRAINT `requisicoes_ibfk_3` FOREIGN KEY IF NOT EXISTS (`id_exemplar`) REFERENCES `exemplares` (`id`) ON DELETE SET NULL ON UPDATE CASCADE;",
+            // every title used to be a single copy, so each one gets a copy that its loans now point at,
+            // the statements skip what a run that stopped halfway already did
+            "INSERT INTO exemplares (id_livro) SELECT id FROM livros WHERE NOT EXISTS (SELECT * FROM exemplares e WHERE e.id_livro = livros.id)",
+            "UPDATE requisicoes SET id_exemplar = (SELECT MIN(exemplares.id) FROM exemplares WHERE exemplares.id_livro = requisicoes.id_livro_requisitado) WHERE id_exemplar IS NULL",
+            "UPDATE exemplares SET codigo = CONCAT('EX', LPAD(id, 8, '0')) WHERE codigo IS NULL",
+        ],
+        down: &[
+            "ALTER TABLE `requisicoes` DROP FOREIGN KEY IF EXISTS `requisicoes_ibfk_3`",
+            "ALTER TABLE `requisicoes` DROP KEY IF EXISTS `requisicoes_id_exemplar`",
+            "ALTER TABLE `requisicoes` DROP COLUMN IF EXISTS `id_exemplar`",
+            "DROP TABLE IF EXISTS `exemplares`",
+        ],
+    },
+    Migration {
+        version: 12,
+        name: "requisicoes_data_limite",
+        up: &[
+            "ALTER TABLE `requisicoes`
+  ADD COLUMN IF NOT EXISTS `data_limite` datetime DEFAULT NULL AFTER `data_requisicao`;",
+            // loans made before due dates existed get the library default period
+            "UPDATE `requisicoes`
+  SET `data_limite` = DATE_ADD(`data_requisicao`, INTERVAL COALESCE((SELECT CAST(`valor` AS SIGNED) FROM `configuracoes` WHERE `chave` = 'emprestimo_dias'), 7) DAY)
+  WHERE `data_limite` IS NULL;",
+        ],
+        down: &["ALTER TABLE `requisicoes` DROP COLUMN IF EXISTS `data_limite`"],
+    },
+    Migration {
+        version: 13,
+        name: "prazos_categoria",
+        up: &["CREATE TABLE IF NOT EXISTS `prazos_categoria` (
+  `id_categoria` int(11) NOT NULL,
+  `dias` int(11) NOT NULL,
+  PRIMARY KEY (`id_categoria`),
+  CONSTRAINT `prazos_categoria_ibfk_1` FOREIGN KEY (`id_categoria`) REFERENCES `categorias` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `prazos_categoria`"],
+    },
+    Migration {
+        version: 14,
+        name: "requisicoes_renovacoes",
+        up: &["ALTER TABLE `requisicoes`
+  ADD COLUMN IF NOT EXISTS `renovacoes` int(11) NOT NULL DEFAULT 0 AFTER `data_limite`;"],
+        down: &["ALTER TABLE `requisicoes` DROP COLUMN IF EXISTS `renovacoes`"],
+    },
+    Migration {
+        version: 15,
+        name: "historico_requisicoes",
+        up: &["CREATE TABLE IF NOT EXISTS `historico_requisicoes` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_requisicao` int(11) NOT NULL,
+  `id_bibliotecario` int(11) DEFAULT NULL,
+  `evento` varchar(32) NOT NULL,
+  `data_limite_anterior` datetime DEFAULT NULL,
+  `data_limite` datetime DEFAULT NULL,
+  `data` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `historico_requisicoes_id_requisicao` (`id_requisicao`),
+  KEY `historico_requisicoes_id_bibliotecario` (`id_bibliotecario`),
+  CONSTRAINT `historico_requisicoes_ibfk_1` FOREIGN KEY (`id_requisicao`) REFERENCES `requisicoes` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `historico_requisicoes_ibfk_2` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE SET NULL ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `historico_requisicoes`"],
+    },
+    Migration {
+        version: 16,
+        name: "reservas",
+        up: &["CREATE TABLE IF NOT EXISTS `reservas` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_livro` int(11) NOT NULL,
+  `id_leitor` int(11) NOT NULL,
+  `estado` varchar(16) NOT NULL DEFAULT 'pendente',
+  `data_reserva` datetime NOT NULL,
+  `data_disponivel` datetime DEFAULT NULL,
+  `data_expiracao` datetime DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `reservas_id_livro` (`id_livro`, `estado`),
+  KEY `reservas_id_leitor` (`id_leitor`),
+  CONSTRAINT `reservas_ibfk_1` FOREIGN KEY (`id_livro`) REFERENCES `livros` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `reservas_ibfk_2` FOREIGN KEY (`id_leitor`) REFERENCES `leitores` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;"],
+        down: &["DROP TABLE IF EXISTS `reservas`"],
+    },
+    Migration {
+        version: 17,
+        name: "movimentos_leitor",
+        up: &[
+            "CREATE TABLE IF NOT EXISTS `movimentos_leitor` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `id_leitor` int(11) NOT NULL,
+  `id_requisicao` int(11) DEFAULT NULL,
+  `id_bibliotecario` int(11) DEFAULT NULL,
+  `tipo` varchar(16) NOT NULL,
+  `valor` int(11) NOT NULL,
+  `descricao` varchar(255) DEFAULT NULL,
+  `data` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `movimentos_leitor_id_leitor` (`id_leitor`),
+  KEY `movimentos_leitor_id_requisicao` (`id_requisicao`),
+  KEY `movimentos_leitor_id_bibliotecario` (`id_bibliotecario`),
+  CONSTRAINT `movimentos_leitor_ibfk_1` FOREIGN KEY (`id_leitor`) REFERENCES `leitores` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `movimentos_leitor_ibfk_2` FOREIGN KEY (`id_requisicao`) REFERENCES `requisicoes` (`id`) ON DELETE SET NULL ON UPDATE CASCADE,
+  CONSTRAINT `movimentos_leitor_ibfk_3` FOREIGN KEY (`id_bibliotecario`) REFERENCES `bibliotecarios` (`id`) ON DELETE SET NULL ON UPDATE CASCADE
+) ENGINE=InnoDB AUTO_INCREMENT=1 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+            "INSERT INTO `permissoes` (id, acao, label) VALUES
+(30,'perdoar_multa','Perdoar Multa')
+ON DUPLICATE KEY UPDATE id=VALUES(id);",
+            // granted like ver_auditoria in migration 10
+            "INSERT INTO `cargos` (nome, permissao) SELECT DISTINCT nome, 30 FROM `cargos` WHERE permissao = 25
+  AND NOT EXISTS (SELECT * FROM `cargos` c WHERE c.nome = cargos.nome AND c.permissao = 30);",
+        ],
+        down: &[
+            "DELETE FROM `cargos` WHERE `permissao` = 30",
+            "DELETE FROM `permissoes` WHERE `id` = 30",
+            "DROP TABLE IF EXISTS `movimentos_leitor`",
+        ],
+    },
+    Migration {
+        version: 18,
+        name: "tipos_leitor",
+        up: &[
+            // empty limits fall back to the library loan policy
+            "CREATE TABLE IF NOT EXISTS `tipos_leitor` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `nome` varchar(64) NOT NULL,
+  `max_requisicoes` int(11) DEFAULT NULL,
+  `dias_emprestimo` int(11) DEFAULT NULL,
+  `max_renovacoes` int(11) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `tipos_leitor_nome` (`nome`)
+) ENGINE=InnoDB AUTO_INCREMENT=4 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+            "INSERT INTO `tipos_leitor` (id, nome, max_requisicoes, dias_emprestimo, max_renovacoes) VALUES
+(1,'Aluno',3,NULL,NULL),
+(2,'Professor',10,30,3),
+(3,'Externo',2,NULL,1)
+ON DUPLICATE KEY UPDATE id=VALUES(id);",
+            "ALTER TABLE `leitores`
+  ADD COLUMN IF NOT EXISTS `id_tipo` int(11) NOT NULL DEFAULT 1,
+  ADD KEY IF NOT EXISTS `leitores_id_tipo` (`id_tipo`),
+  ADD CONSTRAINT `leitores_ibfk_1` FOREIGN KEY IF NOT EXISTS (`id_tipo`) REFERENCES `tipos_leitor` (`id`) ON UPDATE CASCADE;",
+        ],
+        down: &[
+            "ALTER TABLE `leitores` DROP FOREIGN KEY IF EXISTS `leitores_ibfk_1`",
+            "ALTER TABLE `leitores` DROP KEY IF EXISTS `leitores_id_tipo`",
+            "ALTER TABLE `leitores` DROP COLUMN IF EXISTS `id_tipo`",
+            "DROP TABLE IF EXISTS `tipos_leitor`",
+        ],
+    },
+    Migration {
+        version: 19,
+        name: "leitores_numero_cartao",
+        up: &[
+            "ALTER TABLE `leitores`
+  ADD COLUMN IF NOT EXISTS `numero_cartao` varchar(64) DEFAULT NULL,
+  ADD UNIQUE KEY IF NOT EXISTS `leitores_numero_cartao` (`numero_cartao`);",
+            "UPDATE leitores SET numero_cartao = CONCAT('LE', LPAD(id, 8, '0')) WHERE numero_cartao IS NULL",
+        ],
+        down: &[
+            "ALTER TABLE `leitores` DROP KEY IF EXISTS `leitores_numero_cartao`",
+            "ALTER TABLE `leitores` DROP COLUMN IF EXISTS `numero_cartao`",
+        ],
+    },
+    Migration {
+        version: 20,
+        name: "requisicoes_estado_devolucao",
+        up: &["ALTER TABLE `requisicoes`
+  ADD COLUMN IF NOT EXISTS `notas_devolucao` varchar(255) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `danificado` tinyint(1) NOT NULL DEFAULT 0;"],
+        down: &[
+            "ALTER TABLE `requisicoes` DROP COLUMN IF EXISTS `danificado`",
+            "ALTER TABLE `requisicoes` DROP COLUMN IF EXISTS `notas_devolucao`",
+        ],
+    },
+    Migration {
+        version: 21,
+        name: "livros_isbn",
+        // both forms are stored so a book can be found by whichever one is printed on it
+        up: &["ALTER TABLE `livros`
+  ADD COLUMN IF NOT EXISTS `isbn10` varchar(10) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `isbn13` varchar(13) DEFAULT NULL,
+  ADD UNIQUE KEY IF NOT EXISTS `livros_isbn10` (`isbn10`),
+  ADD UNIQUE KEY IF NOT EXISTS `livros_isbn13` (`isbn13`);"],
+        down: &[
+            "ALTER TABLE `livros` DROP KEY IF EXISTS `livros_isbn13`",
+            "ALTER TABLE `livros` DROP KEY IF EXISTS `livros_isbn10`",
+            "ALTER TABLE `livros` DROP COLUMN IF EXISTS `isbn13`",
+            "ALTER TABLE `livros` DROP COLUMN IF EXISTS `isbn10`",
+        ],
+    },
+    Migration {
+        version: 22,
+        name: "livros_autores",
+        up: &[
+            // the same person can appear more than once on a book, as author and illustrator for example
+            "CREATE TABLE IF NOT EXISTS `livros_autores` (
+  `id_livro` int(11) NOT NULL,
+  `id_autor` int(11) NOT NULL,
+  `papel` varchar(32) NOT NULL DEFAULT 'autor',
+  `ordem` int(11) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`id_livro`, `id_autor`, `papel`),
+  KEY `livros_autores_id_autor` (`id_autor`),
+  CONSTRAINT `livros_autores_ibfk_1` FOREIGN KEY (`id_livro`) REFERENCES `livros` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT `livros_autores_ibfk_2` FOREIGN KEY (`id_autor`) REFERENCES `autores` (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;",
+            // the single author of each book becomes its first author before the column goes away
+            "INSERT IGNORE INTO livros_autores (id_livro, id_autor, papel, ordem) SELECT id, id_autor, 'autor', 0 FROM livros WHERE id_autor IS NOT NULL",
+            "ALTER TABLE livros DROP FOREIGN KEY IF EXISTS livros_ibfk_3",
+            "ALTER TABLE livros DROP COLUMN IF EXISTS id_autor",
+        ],
+        // books keep only their first credited author
+        down: &[
+            "ALTER TABLE `livros`
+  ADD COLUMN IF NOT EXISTS `id_autor` int(11) DEFAULT NULL AFTER `ano_edicao`,
+  ADD KEY IF NOT EXISTS `livros_id_autor` (`id_autor`);",
+            "UPDATE livros SET id_autor = (SELECT id_autor FROM livros_autores WHERE livros_autores.id_livro = livros.id ORDER BY papel <> 'autor', ordem LIMIT 1)",
+            "ALTER TABLE `livros`
+  ADD CONSTRAINT `livros_ibfk_3` FOREIGN KEY IF NOT EXISTS (`id_autor`) REFERENCES `autores` (`id`) ON DELETE CASCADE ON UPDATE CASCADE;",
+            "DROP TABLE IF EXISTS `livros_autores`",
+        ],
+    },
+];
+
+async fn table_exists(table: &str, conn: &mut MySqlConnection) -> Result<bool, String> {
+    sqlx::query_scalar(
+        "SELECT EXISTS(SELECT * FROM information_schema.TABLES WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?)",
+    )
+    .bind(table)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar tabelas: {}", e);
+        format!("Falha ao consultar tabelas: {}", e)
+    })
+}
+
+// the shipped dumps are the only databases from before schema versions, they have the tables of the first one
+async fn legacy_version(conn: &mut MySqlConnection) -> Result<i32, String> {
+    if table_exists("livros", conn).await? {
+        Ok(1)
+    } else {
+        Ok(0)
+    }
+}
+
+// a database without recorded versions is either empty or was created before they existed
+async fn current_version(conn: &mut MySqlConnection) -> Result<i32, String> {
+    let version: Option<i32> = if table_exists("versao_esquema", conn).await? {
+        sqlx::query_scalar("SELECT MAX(versao) FROM versao_esquema")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar versão do esquema: {}", e);
+                format!("Falha ao consultar versão do esquema: {}", e)
+            })?
+    } else {
+        None
+    };
+
+    match version {
+        Some(version) => Ok(version),
+        None => legacy_version(conn).await,
+    }
+}
+
+async fn record_version(migration: &Migration, conn: &mut MySqlConnection) -> Result<(), String> {
+    sqlx::query("INSERT INTO versao_esquema (versao, nome, data_aplicacao) VALUES (?, ?, NOW())")
+        .bind(migration.version)
+        .bind(migration.name)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao registar versão do esquema: {}", e);
+            format!("Falha ao registar versão do esquema: {}", e)
+        })?;
+
+    Ok(())
+}
+
+// creates the version table and records what a database from before it already has
+async fn prepare(conn: &mut MySqlConnection) -> Result<i32, String> {
+    let version = current_version(conn).await?;
+
+    sqlx::query(VERSION_TABLE)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao criar tabela versao_esquema: {}", e);
+            format!("Falha ao criar tabela versao_esquema: {}", e)
+        })?;
+
+    let recorded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM versao_esquema")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar versão do esquema: {}", e);
+            format!("Falha ao consultar versão do esquema: {}", e)
+        })?;
+
+    if recorded == 0 {
+        for migration in MIGRATIONS.iter().take_while(|m| m.version <= version) {
+            record_version(migration, conn).await?;
+        }
+    }
+
+    Ok(version)
+}
+
+async fn run_statements(
+    migration: &Migration,
+    statements: &[&str],
+    conn: &mut MySqlConnection,
+) -> Result<(), String> {
+    for statement in statements {
+        sqlx::query(statement)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| {
+                tracing::error!(
+                    "Falha na migração {} ({}): {}",
+                    migration.version,
+                    migration.name,
+                    e
+                );
+                format!(
+                    "Falha na migração {} ({}): {}",
+                    migration.version, migration.name, e
+                )
+            })?;
+    }
+
+    Ok(())
+}
+
+async fn migrate_up(conn: &mut MySqlConnection) -> Result<(), String> {
+    let version = prepare(conn).await?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        tracing::info!(
+            "A aplicar migração {} ({})",
+            migration.version,
+            migration.name
+        );
+
+        run_statements(migration, migration.up, conn).await?;
+        record_version(migration, conn).await?;
+    }
+
+    Ok(())
+}
+
+async fn migrate_down(target: i32, conn: &mut MySqlConnection) -> Result<(), String> {
+    let version = prepare(conn).await?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|m| m.version > target && m.version <= version)
+    {
+        tracing::info!(
+            "A reverter migração {} ({})",
+            migration.version,
+            migration.name
+        );
+
+        run_statements(migration, migration.down, conn).await?;
+
+        sqlx::query("DELETE FROM versao_esquema WHERE versao = ?")
+            .bind(migration.version)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao registar versão do esquema: {}", e);
+                format!("Falha ao registar versão do esquema: {}", e)
+            })?;
+    }
+
+    Ok(())
+}
+
+// the lock belongs to the connection, so everything runs on the one that took it and releases it even on failure
+async fn acquire_lock(pool: &Pool<MySql>) -> Result<PoolConnection<MySql>, String> {
+    let mut conn = pool.acquire().await.map_err(|e| {
+        tracing::error!("Falha ao obter conexão: {}", e);
+        format!("Falha ao obter conexão: {}", e)
+    })?;
+
+    let locked: Option<bool> = sqlx::query_scalar("SELECT GET_LOCK(?, ?)")
+        .bind(LOCK_NAME)
+        .bind(LOCK_TIMEOUT_SECONDS)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao bloquear migrações: {}", e);
+            format!("Falha ao bloquear migrações: {}", e)
+        })?;
+
+    if locked != Some(true) {
+        return Err("Outra instância está a atualizar a base de dados".to_string());
+    }
+
+    Ok(conn)
+}
+
+async fn release_lock(conn: &mut MySqlConnection) -> Result<(), String> {
+    sqlx::query("SELECT RELEASE_LOCK(?)")
+        .bind(LOCK_NAME)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao desbloquear migrações: {}", e);
+            format!("Falha ao desbloquear migrações: {}", e)
+        })?;
+
+    Ok(())
+}
+
+pub async fn run_migrations(pool: &Pool<MySql>) -> Result<(), String> {
+    let mut conn = acquire_lock(pool).await?;
+
+    let result = migrate_up(&mut conn).await;
+
+    release_lock(&mut conn).await?;
+
+    result
+}
+
+// reads the schema without changing it, not even to record the versions of an older database
+pub async fn pending_migrations(pool: &Pool<MySql>) -> Result<Vec<PendingMigration>, String> {
+    let mut conn = pool.acquire().await.map_err(|e| {
+        tracing::error!("Falha ao obter conexão: {}", e);
+        format!("Falha ao obter conexão: {}", e)
+    })?;
+
+    let version = current_version(&mut conn).await?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| m.version > version)
+        .map(|m| PendingMigration {
+            versao: m.version,
+            nome: m.name,
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn get_pending_migrations(db_url: String) -> Result<Vec<PendingMigration>, String> {
+    let pool = connect(&db_url, 1).await?;
+
+    let pending = pending_migrations(&pool).await;
+
+    pool.close().await;
+
+    pending
+}
+
+// meant for going back to an older release of the app, init applies the migrations again on the next connection
+#[tauri::command]
+pub async fn revert_migrations(
+    token: String,
+    version: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    // the baseline holds the librarians themselves, reverting it is done by dropping the database
+    if version < 1 {
+        return Err("Não é possível reverter o esquema inicial"
+            .to_string()
+            .into());
+    }
+
+    let mut conn = acquire_lock(pool).await?;
+
+    let result = migrate_down(version, &mut conn).await;
+
+    release_lock(&mut conn).await?;
+
+    Ok(result?)
+}
@@ -0,0 +1,281 @@
+use rand::{rngs::OsRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::{
+    errors::CommandError,
+    jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
+    settings::{get_setting, set_setting},
+    Database,
+};
+
+const UPPERCASE: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+const LOWERCASE: &[u8] = b"abcdefghijkmnopqrstuvwxyz";
+const DIGITS: &[u8] = b"23456789";
+const SYMBOLS: &[u8] = b"!@#$%&*?-_";
+const TEMPORARY_PASSWORD_LENGTH: usize = 12;
+
+#[derive(Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    pub min_length: i32,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub history: i32,
+}
+
+pub async fn load_policy(conn: &Pool<MySql>) -> Result<PasswordPolicy, String> {
+    Ok(PasswordPolicy {
+        min_length: get_setting("password_tamanho_minimo", 8, conn).await?,
+        require_uppercase: get_setting("password_maiusculas", true, conn).await?,
+        require_lowercase: get_setting("password_minusculas", true, conn).await?,
+        require_digit: get_setting("password_digitos", true, conn).await?,
+        require_symbol: get_setting("password_simbolos", false, conn).await?,
+        history: get_setting("password_historico", 3, conn).await?,
+    })
+}
+
+pub fn validate_password(password: &str, policy: &PasswordPolicy) -> Result<(), String> {
+    if (password.chars().count() as i32) < policy.min_length {
+        return Err(format!(
+            "A password tem de ter pelo menos {} caracteres",
+            policy.min_length
+        ));
+    }
+
+    if policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+        return Err("A password tem de ter pelo menos uma letra maiúscula".to_string());
+    }
+
+    if policy.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+        return Err("A password tem de ter pelo menos uma letra minúscula".to_string());
+    }
+
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err("A password tem de ter pelo menos um número".to_string());
+    }
+
+    if policy.require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+        return Err("A password tem de ter pelo menos um símbolo".to_string());
+    }
+
+    Ok(())
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| {
+        tracing::error!("Falha ao encriptar password: {}", e);
+        format!("Falha ao encriptar password: {}", e)
+    })
+}
+
+pub async fn check_history(
+    librarian_id: i32,
+    password: &str,
+    policy: &PasswordPolicy,
+    conn: &Pool<MySql>,
+) -> Result<(), String> {
+    if policy.history <= 0 {
+        return Ok(());
+    }
+
+    let previous: Vec<String> = sqlx::query_scalar(
+        "SELECT password FROM historico_passwords WHERE id_bibliotecario = ? ORDER BY data_alteracao DESC, id DESC LIMIT ?",
+    )
+    .bind(librarian_id)
+    .bind(policy.history)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar histórico de passwords: {}", e);
+        format!("Falha ao consultar histórico de passwords: {}", e)
+    })?;
+
+    for hash in previous {
+        if bcrypt::verify(password, &hash).unwrap_or(false) {
+            return Err(format!(
+                "A password não pode ser igual às últimas {} passwords",
+                policy.history
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn record_password(
+    librarian_id: i32,
+    hashed_password: &str,
+    conn: &mut sqlx::MySqlConnection,
+) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO historico_passwords (id_bibliotecario, password, data_alteracao) VALUES (?, ?, NOW())",
+    )
+    .bind(librarian_id)
+    .bind(hashed_password)
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao guardar histórico de passwords: {}", e);
+        format!("Falha ao guardar histórico de passwords: {}", e)
+    })?;
+
+    Ok(())
+}
+
+// has one character of every class and is at least as long as the policy asks, so the policy accepts it
+pub fn generate_temporary_password(policy: &PasswordPolicy) -> String {
+    let length = TEMPORARY_PASSWORD_LENGTH.max(policy.min_length.max(0) as usize);
+    let classes = [UPPERCASE, LOWERCASE, DIGITS, SYMBOLS];
+    let all: Vec<u8> = classes.concat();
+
+    let mut password: Vec<u8> = classes
+        .iter()
+        .map(|class| *class.choose(&mut OsRng).unwrap())
+        .collect();
+
+    while password.len() < length {
+        password.push(*all.choose(&mut OsRng).unwrap());
+    }
+
+    password.shuffle(&mut OsRng);
+
+    String::from_utf8(password).unwrap()
+}
+
+#[tauri::command]
+pub async fn get_password_policy(
+    token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<PasswordPolicy, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    Ok(load_policy(pool).await?)
+}
+
+#[tauri::command]
+pub async fn update_password_policy(
+    token: String,
+    policy: PasswordPolicy,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    if policy.min_length < 1 || policy.history < 0 {
+        return Err("Política de passwords inválida".to_string().into());
+    }
+
+    set_setting("password_tamanho_minimo", policy.min_length, pool).await?;
+    set_setting("password_maiusculas", policy.require_uppercase, pool).await?;
+    set_setting("password_minusculas", policy.require_lowercase, pool).await?;
+    set_setting("password_digitos", policy.require_digit, pool).await?;
+    set_setting("password_simbolos", policy.require_symbol, pool).await?;
+    set_setting("password_historico", policy.history, pool).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict_policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+            history: 3,
+        }
+    }
+
+    #[test]
+    fn names_the_rule_a_password_breaks() {
+        let policy = strict_policy();
+
+        assert!(validate_password("Biblio2024!", &policy).is_ok());
+        assert_eq!(
+            validate_password("Bi2024!", &policy).unwrap_err(),
+            "A password tem de ter pelo menos 8 caracteres"
+        );
+        assert_eq!(
+            validate_password("biblio2024!", &policy).unwrap_err(),
+            "A password tem de ter pelo menos uma letra maiúscula"
+        );
+        assert_eq!(
+            validate_password("BIBLIO2024!", &policy).unwrap_err(),
+            "A password tem de ter pelo menos uma letra minúscula"
+        );
+        assert_eq!(
+            validate_password("Biblioteca!", &policy).unwrap_err(),
+            "A password tem de ter pelo menos um número"
+        );
+        assert_eq!(
+            validate_password("Biblio2024", &policy).unwrap_err(),
+            "A password tem de ter pelo menos um símbolo"
+        );
+    }
+
+    #[test]
+    fn length_counts_characters_and_rules_can_be_turned_off() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            require_uppercase: false,
+            require_lowercase: false,
+            require_digit: false,
+            require_symbol: false,
+            history: 0,
+        };
+
+        // 7 characters but 14 bytes
+        assert!(validate_password("ççççççç", &policy).is_err());
+        assert!(validate_password("çççççççç", &policy).is_ok());
+        // accented letters are not symbols
+        assert!(validate_password("Ação2024", &strict_policy()).is_err());
+    }
+
+    #[test]
+    fn temporary_passwords_meet_the_strict_policy() {
+        for _ in 0..100 {
+            let password = generate_temporary_password(&strict_policy());
+
+            assert_eq!(password.len(), TEMPORARY_PASSWORD_LENGTH);
+            assert!(
+                validate_password(&password, &strict_policy()).is_ok(),
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn temporary_passwords_grow_with_the_minimum_length() {
+        let policy = PasswordPolicy {
+            min_length: 20,
+            ..strict_policy()
+        };
+        let password = generate_temporary_password(&policy);
+
+        assert_eq!(password.len(), 20);
+        assert!(
+            validate_password(&password, &policy).is_ok(),
+            "{}",
+            password
+        );
+    }
+}
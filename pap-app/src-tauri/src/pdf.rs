@@ -0,0 +1,231 @@
+// just enough of PDF 1.4 to draw filled rectangles and Helvetica text, which is all the labels need
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+// advance widths of Helvetica for the printable ASCII characters, in thousandths of the font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+// accented letters are about as wide as the letters they are based on
+const DEFAULT_WIDTH: u16 = 556;
+
+pub fn mm(value: f64) -> f64 {
+    value * POINTS_PER_MM
+}
+
+pub fn text_width(text: &str, size: f64) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => HELVETICA_WIDTHS[c as usize - 32],
+            _ => DEFAULT_WIDTH,
+        } as f64)
+        .sum::<f64>()
+        * size
+        / 1000.0
+}
+
+// shortens the text with an ellipsis until it fits in the given width
+pub fn fit_text(text: &str, size: f64, max_width: f64) -> String {
+    if text_width(text, size) <= max_width {
+        return text.to_string();
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+
+    while !chars.is_empty() {
+        chars.pop();
+        let shortened = format!("{}...", chars.iter().collect::<String>().trim_end());
+
+        if text_width(&shortened, size) <= max_width {
+            return shortened;
+        }
+    }
+
+    String::new()
+}
+
+// the standard fonts use WinAnsiEncoding, which matches Latin-1 for the accented letters
+fn encode_text(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => bytes.extend([b'\\', c as u8]),
+            '€' => bytes.push(0x80),
+            ' '..='~' | '\u{a0}'..='\u{ff}' => bytes.push(c as u32 as u8),
+            _ => bytes.push(b'?'),
+        }
+    }
+
+    bytes
+}
+
+#[derive(Default)]
+pub struct PdfPage {
+    content: Vec<u8>,
+}
+
+// coordinates are in points from the bottom left corner of the page
+impl PdfPage {
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.content
+            .extend(format!("{:.3} {:.3} {:.3} {:.3} re f\n", x, y, width, height).into_bytes());
+    }
+
+    pub fn text(&mut self, x: f64, y: f64, size: f64, text: &str) {
+        self.content
+            .extend(format!("BT /F1 {:.2} Tf {:.3} {:.3} Td (", size, x, y).into_bytes());
+        self.content.extend(encode_text(text));
+        self.content.extend(b") Tj ET\n");
+    }
+}
+
+pub struct PdfDocument {
+    width: f64,
+    height: f64,
+    pages: Vec<PdfPage>,
+}
+
+impl PdfDocument {
+    pub fn new(width: f64, height: f64) -> PdfDocument {
+        PdfDocument {
+            width,
+            height,
+            pages: Vec::new(),
+        }
+    }
+
+    pub fn page(&mut self, index: usize) -> &mut PdfPage {
+        while self.pages.len() <= index {
+            self.pages.push(PdfPage::default());
+        }
+
+        &mut self.pages[index]
+    }
+
+    // objects 1 to 3 are the catalog, the page tree and the font, each page adds itself and its content
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..self.pages.len())
+                    .map(|i| format!("{} 0 R", 4 + i * 2))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                self.pages.len()
+            )
+            .into_bytes(),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .to_vec(),
+        ];
+
+        for (i, page) in self.pages.iter().enumerate() {
+            objects.push(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.3} {:.3}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                    self.width,
+                    self.height,
+                    5 + i * 2
+                )
+                .into_bytes(),
+            );
+
+            let mut stream = format!("<< /Length {} >>\nstream\n", page.content.len()).into_bytes();
+            stream.extend(&page.content);
+            stream.extend(b"\nendstream");
+            objects.push(stream);
+        }
+
+        let mut output = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(output.len());
+            output.extend(format!("{} 0 obj\n", i + 1).into_bytes());
+            output.extend(object);
+            output.extend(b"\nendobj\n");
+        }
+
+        let xref = output.len();
+        output.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+
+        for offset in offsets {
+            output.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+        }
+
+        output.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                xref
+            )
+            .into_bytes(),
+        );
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap()
+    }
+
+    #[test]
+    fn text_width_uses_the_helvetica_metrics() {
+        // H 722, e 556, l 222, l 222, o 556
+        assert!((text_width("Hello", 10.0) - 22.78).abs() < 1e-9);
+        assert_eq!(text_width("ã", 1000.0), text_width("a", 1000.0));
+    }
+
+    #[test]
+    fn fit_text_shortens_with_an_ellipsis() {
+        assert_eq!(fit_text("Hello world", 10.0, 100.0), "Hello world");
+
+        // the space left before the ellipsis is dropped
+        let width = text_width("Hello...", 10.0);
+        assert_eq!(fit_text("Hello world", 10.0, width), "Hello...");
+        assert_eq!(fit_text("Hello world", 10.0, width - 0.01), "Hell...");
+
+        // nothing is drawn when not even the ellipsis fits
+        assert_eq!(fit_text("Hello world", 10.0, 1.0), "");
+    }
+
+    #[test]
+    fn xref_offsets_point_at_their_objects() {
+        let mut document = PdfDocument::new(mm(210.0), mm(297.0));
+        document.page(0).rect(10.0, 10.0, 20.0, 20.0);
+        document.page(1).text(10.0, 10.0, 8.0, "Ação (1)");
+        let bytes = document.to_bytes();
+
+        let tail = &bytes[find(&bytes, b"startxref\n") + 10..];
+        let xref: usize = std::str::from_utf8(&tail[..find(tail, b"\n")])
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(bytes[xref..].starts_with(b"xref\n0 8\n0000000000 65535 f \n"));
+
+        // every entry is exactly 20 bytes long, the free entry of object 0 comes first
+        let entries = &bytes[xref + "xref\n0 8\n".len() + 20..];
+
+        for object in 1..8 {
+            let entry = &entries[(object - 1) * 20..object * 20];
+            assert!(entry.ends_with(b" 00000 n \n"));
+
+            let offset: usize = std::str::from_utf8(&entry[..10]).unwrap().parse().unwrap();
+            assert!(bytes[offset..].starts_with(format!("{} 0 obj\n", object).as_bytes()));
+        }
+
+        assert!(bytes.ends_with(b"%%EOF\n"));
+    }
+}
@@ -1,38 +1,120 @@
-use tokio::sync::Mutex;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
 
-use crate::{db_structs::Permissao, jwt::verify_jwt, Database};
+use crate::{
+    audit::{record_audit, UPDATE},
+    db_structs::Permissao,
+    errors::CommandError,
+    jwt::{get_from_jwt, verify_jwt, TOKEN_EXPIRED},
+    roles::role_snapshot,
+    Database,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "detail")]
+pub enum AuthError {
+    ExpiredToken,
+    InvalidToken(String),
+    Forbidden(String),
+    Database(String),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::ExpiredToken => write!(f, "Falha ao verificar token: {}", TOKEN_EXPIRED),
+            AuthError::InvalidToken(e) => write!(f, "Falha ao verificar token: {}", e),
+            AuthError::Forbidden(acao) => {
+                write!(f, "Sem permissão para realizar esta ação ({})", acao)
+            }
+            AuthError::Database(e) => write!(f, "Falha ao consultar permissões: {}", e),
+        }
+    }
+}
+
+impl AuthError {
+    // an expired token is told apart so the frontend refreshes it instead of logging out
+    pub fn from_token_error(e: String) -> AuthError {
+        if e == TOKEN_EXPIRED {
+            AuthError::ExpiredToken
+        } else {
+            AuthError::InvalidToken(e)
+        }
+    }
+}
+
+pub fn librarian_id(token: &str) -> Result<i32, AuthError> {
+    get_from_jwt(token)
+        .map_err(AuthError::from_token_error)?
+        .get("id")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or_else(|| AuthError::InvalidToken("token inválido".to_string()))
+}
+
+pub async fn has_permission(
+    token: &str,
+    acao: &str,
+    conn: &Pool<MySql>,
+) -> Result<bool, AuthError> {
+    verify_jwt(token, conn)
+        .await
+        .map_err(AuthError::from_token_error)?;
+
+    let id = librarian_id(token)?;
+
+    // the role is read from the database so role changes apply to tokens already issued
+    let count: i32 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM cargos
+        INNER JOIN permissoes ON permissoes.id = cargos.permissao
+        INNER JOIN bibliotecarios ON bibliotecarios.cargo = cargos.nome
+        WHERE bibliotecarios.id = ? AND permissoes.acao = ?",
+    )
+    .bind(id)
+    .bind(acao)
+    .fetch_one(conn)
+    .await
+    .map_err(|e| AuthError::Database(e.to_string()))?;
+
+    Ok(count > 0)
+}
+
+pub async fn verify_permission(
+    token: &str,
+    acao: &str,
+    conn: &Pool<MySql>,
+) -> Result<(), AuthError> {
+    if !has_permission(token, acao, conn).await? {
+        return Err(AuthError::Forbidden(acao.to_string()));
+    }
+
+    Ok(())
+}
 
 #[tauri::command]
 pub async fn add_permission_to_role(
     token: String,
     role: String,
     permissions: Vec<i32>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<String, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
-
-    if token == "first_librarian" {
-        let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM bibliotecarios")
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar: {}", e);
-                format!("Falha ao consultar: {}", e)
-            })?;
+    state: tauri::State<'_, Database>,
+) -> Result<String, CommandError> {
+    let pool = &state.pool().await?;
 
-        if count > 0 {
-            return Err("Já existe um bibliotecário".to_string());
-        }
-    } else {
-        verify_jwt(&token, &pool).await.map_err(|e| {
-            tracing::error!("Falha ao verificar token: {}", e);
-            format!("Falha ao verificar token: {}", e)
+    verify_permission(&token, "atualizar_cargo", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
         })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before = role_snapshot(&role, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Cargo não existe".to_string().into());
     }
 
     for permission in permissions {
@@ -40,7 +122,7 @@ pub async fn add_permission_to_role(
             sqlx::query_scalar("SELECT COUNT(*) FROM cargos WHERE nome = ? AND permissao = ?")
                 .bind(&role)
                 .bind(permission)
-                .fetch_one(pool)
+                .fetch_one(&mut *tx)
                 .await
                 .map_err(|e| {
                     tracing::error!("Falha ao consultar: {}", e);
@@ -48,13 +130,13 @@ pub async fn add_permission_to_role(
                 })?;
 
         if role_count > 0 {
-            return Err("Cargo já tem essa permissão".to_string());
+            return Err("Cargo já tem essa permissão".to_string().into());
         }
 
         sqlx::query("INSERT INTO cargos (nome, permissao) VALUES (?, ?)")
             .bind(&role)
             .bind(permission)
-            .execute(pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao inserir permissão: {}", e);
@@ -62,19 +144,32 @@ pub async fn add_permission_to_role(
             })?;
     }
 
+    let after = role_snapshot(&role, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "cargos",
+        &role,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(role)
 }
 
 #[tauri::command]
 pub async fn get_permissions(
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Permissao>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Permissao>, CommandError> {
+    let pool = &state.pool().await?;
 
     let permissions = sqlx::query_as::<_, Permissao>("SELECT * FROM permissoes")
         .fetch_all(pool)
@@ -1,8 +1,11 @@
-use tokio::sync::Mutex;
-
 use crate::{
-    db_structs::{Editora, Livro, LivroAsResponse},
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
+    book_rows::books_query,
+    books::fetch_books,
+    db_structs::{Editora, LivroAsResponse},
+    errors::CommandError,
     jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
     Database,
 };
 
@@ -10,18 +13,13 @@ use crate::{
 pub async fn get_publisher_by_id(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Editora, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Editora, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let publisher = sqlx::query_as::<_, Editora>("SELECT * FROM editoras WHERE id = ?")
@@ -34,7 +32,7 @@ pub async fn get_publisher_by_id(
         })?;
 
     if publisher.is_empty() {
-        return Err("Editora não encontrada".to_string());
+        return Err("Editora não encontrada".to_string().into());
     }
 
     Ok(publisher[0].clone())
@@ -43,14 +41,9 @@ pub async fn get_publisher_by_id(
 #[tauri::command]
 pub async fn get_publishers_by_name(
     name: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Editora>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Editora>, CommandError> {
+    let pool = &state.pool().await?;
 
     let publishers =
         sqlx::query_as::<_, Editora>("SELECT * FROM editoras WHERE LOWER(nome) LIKE LOWER(?)")
@@ -69,100 +62,18 @@ pub async fn get_publishers_by_name(
 pub async fn get_books_by_publisher_id(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<LivroAsResponse>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<LivroAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let books = sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE id_editora = ?")
-        .bind(id)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar: {}", e);
-            format!("Falha ao consultar: {}", e)
-        })?;
+    let query = books_query("SELECT * FROM livros WHERE id_editora = ?");
 
-    let mut respose_books: Vec<LivroAsResponse> = Vec::new();
-
-    for book in books {
-        let autor = sqlx::query_scalar::<_, String>("SELECT nome FROM autores WHERE id = ?")
-            .bind(book.id_autor)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar autor: {}", e);
-                format!("Falha ao consultar autor: {}", e)
-            })?;
-
-        let categoria = sqlx::query_scalar::<_, String>(
-            "SELECT nome FROM categorias WHERE id = (SELECT id_categoria FROM sub_categorias WHERE id = ?)",
-        )
-        .bind(book.id_sub_categoria)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar categoria: {}", e);
-            format!("Falha ao consultar categoria: {}", e)
-        })?;
-
-        let sub_categoria =
-            sqlx::query_scalar::<_, String>("SELECT nome FROM sub_categorias WHERE id = ?")
-                .bind(book.id_sub_categoria)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Falha ao consultar sub-categoria: {}", e);
-                    format!("Falha ao consultar sub-categoria: {}", e)
-                })?;
-
-        let publisher = sqlx::query_scalar::<_, String>("SELECT nome FROM editoras WHERE id = ?")
-            .bind(book.id_editora)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar editora: {}", e);
-                format!("Falha ao consultar editora: {}", e)
-            })?;
-
-        let is_requested: i32 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL",
-    )
-    .bind(book.id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Falha ao consultar requisição: {}", e);
-        format!("Falha ao consultar requisição: {}", e)
-    })?;
-
-        respose_books.push(LivroAsResponse {
-            id: book.id,
-            autor: Some(autor),
-            categoria: Some(categoria),
-            sub_categoria: Some(sub_categoria),
-            ano_edicao: book.ano_edicao,
-            img_url: book.img_url,
-            resumo: book.resumo,
-            autor_id: book.id_autor,
-            editora: publisher,
-            idioma: book.idioma,
-            nome: book.nome,
-            n_paginas: book.n_paginas,
-            requisitado: is_requested > 0,
-        });
-    }
-
-    Ok(respose_books)
+    Ok(fetch_books(sqlx::query_as(&query).bind(id), pool).await?)
 }
 
 #[tauri::command]
@@ -171,24 +82,17 @@ pub async fn get_publishers(
     limit: i32,
     offset: i32,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Editora>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Editora>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let publishers;
-
-    if search.is_none() {
-        publishers = sqlx::query_as::<_, Editora>("SELECT * FROM editoras LIMIT ? OFFSET ?")
+    let publishers = match search {
+        None => sqlx::query_as::<_, Editora>("SELECT * FROM editoras LIMIT ? OFFSET ?")
             .bind(limit)
             .bind(offset)
             .fetch_all(pool)
@@ -196,12 +100,11 @@ pub async fn get_publishers(
             .map_err(|e| {
                 tracing::error!("Falha ao consultar autores: {}", e);
                 format!("Falha ao consultar autores: {}", e)
-            })?;
-    } else {
-        publishers = sqlx::query_as::<_, Editora>(
+            })?,
+        Some(search) => sqlx::query_as::<_, Editora>(
             "SELECT * FROM editoras WHERE LOWER(nome) LIKE LOWER(?) LIMIT ? OFFSET ?",
         )
-        .bind(format!("%{}%", search.unwrap().to_lowercase()))
+        .bind(format!("%{}%", search.to_lowercase()))
         .bind(limit)
         .bind(offset)
         .fetch_all(pool)
@@ -209,8 +112,8 @@ pub async fn get_publishers(
         .map_err(|e| {
             tracing::error!("Falha ao consultar autores: {}", e);
             format!("Falha ao consultar autores: {}", e)
-        })?;
-    }
+        })?,
+    };
 
     Ok(publishers)
 }
@@ -219,40 +122,34 @@ pub async fn get_publishers(
 pub async fn get_publishers_count(
     token: String,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<i32, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<i32, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let count: i32;
-
-    if search.is_none() {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM editoras")
+    let count: i32 = match search {
+        None => sqlx::query_scalar("SELECT COUNT(*) FROM editoras")
             .fetch_one(pool)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar autores: {}", e);
                 format!("Falha ao consultar autores: {}", e)
-            })?;
-    } else {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM editoras WHERE LOWER(nome) LIKE LOWER(?)")
-            .bind(format!("%{}%", search.unwrap().to_lowercase()))
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar autores: {}", e);
-                format!("Falha ao consultar autores: {}", e)
-            })?;
-    }
+            })?,
+        Some(search) => {
+            sqlx::query_scalar("SELECT COUNT(*) FROM editoras WHERE LOWER(nome) LIKE LOWER(?)")
+                .bind(format!("%{}%", search.to_lowercase()))
+                .fetch_one(pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Falha ao consultar autores: {}", e);
+                    format!("Falha ao consultar autores: {}", e)
+                })?
+        }
+    };
 
     Ok(count)
 }
@@ -265,37 +162,50 @@ pub async fn create_publisher(
     postal_code: String,
     phone: String,
     email: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "criar_editora", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    sqlx::query("INSERT INTO editoras (nome, morada, codigo_postal, telefone, email) VALUES (?, ?, ?, ?, ?)")
+    let result = sqlx::query("INSERT INTO editoras (nome, morada, codigo_postal, telefone, email) VALUES (?, ?, ?, ?, ?)")
         .bind(name)
         .bind(address)
         .bind(postal_code)
         .bind(phone)
         .bind(email)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao criar editora: {}", e);
             format!("Falha ao criar editora: {}", e)
         })?;
 
+    let id = result.last_insert_id() as i32;
+    let after = snapshot::<Editora>("SELECT * FROM editoras WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "editoras", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_publisher(
     token: String,
     id: i32,
@@ -304,20 +214,28 @@ pub async fn update_publisher(
     postal_code: String,
     phone: String,
     email: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "atualizar_editora", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before = snapshot::<Editora>("SELECT * FROM editoras WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Editora não encontrada".to_string().into());
+    }
+
     sqlx::query("UPDATE editoras SET nome = ?, morada = ?, codigo_postal = ?, telefone = ?, email = ? WHERE id = ?")
         .bind(name)
         .bind(address)
@@ -325,13 +243,22 @@ pub async fn update_publisher(
         .bind(phone)
         .bind(email)
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao atualizar editora: {}", e);
             format!("Falha ao atualizar editora: {}", e)
         })?;
 
+    let after = snapshot::<Editora>("SELECT * FROM editoras WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), UPDATE, "editoras", id, before, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -339,28 +266,43 @@ pub async fn update_publisher(
 pub async fn delete_publisher(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "apagar_editora", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before = snapshot::<Editora>("SELECT * FROM editoras WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Editora não encontrada".to_string().into());
+    }
+
     sqlx::query("DELETE FROM editoras WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao deletar editora: {}", e);
             format!("Falha ao deletar editora: {}", e)
         })?;
 
+    record_audit(Some(&token), DELETE, "editoras", id, before, None, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
@@ -0,0 +1,657 @@
+// QR code model 2 in byte mode with error correction level M, versions 1 to 10 hold up to
+// 213 bytes which is plenty for the codes printed on labels and cards
+const MAX_VERSION: usize = 10;
+// error correction codewords per block and number of blocks for level M, indexed by version
+const ECC_CODEWORDS_PER_BLOCK: [usize; MAX_VERSION + 1] =
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+const ECC_BLOCKS: [usize; MAX_VERSION + 1] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+// level M is encoded as 00 in the format information
+const FORMAT_ECC_BITS: u32 = 0;
+
+pub struct QrCode {
+    pub size: usize,
+    modules: Vec<Vec<bool>>,
+    functions: Vec<Vec<bool>>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<QrCode, String> {
+        let version = (1..=MAX_VERSION)
+            .find(|version| {
+                let count_bits = if *version < 10 { 8 } else { 16 };
+
+                data.len() < 1 << count_bits
+                    && 4 + count_bits + data.len() * 8 <= data_codewords(*version) * 8
+            })
+            .ok_or("Os dados são demasiado longos para um código QR")?;
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size,
+            modules: vec![vec![false; size]; size],
+            functions: vec![vec![false; size]; size],
+        };
+
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_ecc_and_interleave(&data_bits(data, version), version));
+
+        // the mask with the lowest penalty is kept, masks are undone by applying them again
+        let mask = (0..8)
+            .min_by_key(|mask| {
+                qr.apply_mask(*mask);
+                qr.draw_format_bits(*mask);
+                let penalty = qr.penalty();
+                qr.apply_mask(*mask);
+                penalty
+            })
+            .unwrap_or_default();
+
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+
+        Ok(qr)
+    }
+
+    pub fn module(&self, x: usize, y: usize) -> bool {
+        self.modules[y][x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y][x] = dark;
+        self.functions[y][x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        for i in 0..self.size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(self.size - 4, 3);
+        self.draw_finder_pattern(3, self.size - 4);
+
+        let positions = alignment_positions(version, self.size);
+        let last = positions.len().saturating_sub(1);
+
+        for (i, x) in positions.iter().enumerate() {
+            for (j, y) in positions.iter().enumerate() {
+                // the corners are taken by the finder patterns
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+
+                self.draw_alignment_pattern(*x, *y);
+            }
+        }
+
+        // reserves the format areas, the real bits are drawn once the mask is chosen
+        self.draw_format_bits(0);
+        self.draw_version(version);
+    }
+
+    fn draw_finder_pattern(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment_pattern(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                self.set_function(
+                    (x as i32 + dx) as usize,
+                    (y as i32 + dy) as usize,
+                    dx.abs().max(dy.abs()) != 1,
+                );
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let data = (FORMAT_ECC_BITS << 3) | mask;
+        let mut remainder = data;
+
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+
+        let bits = ((data << 10) | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+
+        // always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+
+        let mut remainder = version as u32;
+
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+        }
+
+        let bits = ((version as u32) << 12) | remainder;
+
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    // codewords run in two module wide columns zigzagging up and down from the bottom right
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let mut i = 0;
+        let mut right = self.size as i32 - 1;
+
+        while right >= 1 {
+            // the vertical timing pattern is skipped
+            if right == 6 {
+                right = 5;
+            }
+
+            for vertical in 0..self.size {
+                for j in 0..2 {
+                    let x = right as usize - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward {
+                        self.size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+
+                    if !self.functions[y][x] && i < codewords.len() * 8 {
+                        self.modules[y][x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+
+                if invert && !self.functions[y][x] {
+                    self.modules[y][x] = !self.modules[y][x];
+                }
+            }
+        }
+    }
+
+    fn penalty(&self) -> usize {
+        const FINDER_LIKE: [[bool; 11]; 2] = [
+            [
+                true, false, true, true, true, false, true, false, false, false, false,
+            ],
+            [
+                false, false, false, false, true, false, true, true, true, false, true,
+            ],
+        ];
+
+        let size = self.size;
+        let mut penalty = 0;
+        let lines = (0..size)
+            .map(|y| (0..size).map(|x| self.modules[y][x]).collect::<Vec<_>>())
+            .chain((0..size).map(|x| (0..size).map(|y| self.modules[y][x]).collect()));
+
+        for line in lines {
+            // runs of five or more modules of the same colour
+            let mut run = 1;
+
+            for i in 1..=size {
+                if i < size && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+
+                run = 1;
+            }
+
+            // patterns that look like a finder
+            penalty += line
+                .windows(11)
+                .filter(|window| FINDER_LIKE.iter().any(|pattern| window == pattern))
+                .count()
+                * 40;
+        }
+
+        // 2x2 blocks of the same colour
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let colour = self.modules[y][x];
+
+                if self.modules[y][x + 1] == colour
+                    && self.modules[y + 1][x] == colour
+                    && self.modules[y + 1][x + 1] == colour
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // balance between dark and light modules
+        let dark = self.modules.iter().flatten().filter(|dark| **dark).count();
+        let total = size * size;
+        let deviation = (dark * 20).abs_diff(total * 10);
+
+        penalty + deviation.div_ceil(total).saturating_sub(1) * 10
+    }
+}
+
+fn raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        result -= (25 * alignments - 10) * alignments - 55;
+
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+
+    result
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * ECC_BLOCKS[version]
+}
+
+fn alignment_positions(version: usize, size: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+
+    let alignments = version / 7 + 2;
+    let step = (version * 4 + alignments * 2 + 1) / (alignments * 2 - 2) * 2;
+    let mut positions = vec![6];
+    let mut position = size - 7;
+
+    for _ in 0..alignments - 1 {
+        positions.insert(1, position);
+        position -= step;
+    }
+
+    positions
+}
+
+// mode indicator, length and data followed by the terminator and padding codewords
+fn data_bits(data: &[u8], version: usize) -> Vec<u8> {
+    let count_bits = if version < 10 { 8 } else { 16 };
+    let capacity = data_codewords(version);
+    let mut bits: Vec<bool> = Vec::new();
+    let mut push = |value: usize, length: usize| {
+        for i in (0..length).rev() {
+            bits.push((value >> i) & 1 != 0);
+        }
+    };
+
+    push(0b0100, 4);
+    push(data.len(), count_bits);
+
+    for byte in data {
+        push(*byte as usize, 8);
+    }
+
+    let terminator = (capacity * 8 - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.extend(std::iter::repeat_n(false, (8 - bits.len() % 8) % 8));
+
+    let mut codewords: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |value, bit| (value << 1) | *bit as u8))
+        .collect();
+
+    for padding in [0xEC, 0x11].into_iter().cycle() {
+        if codewords.len() >= capacity {
+            break;
+        }
+
+        codewords.push(padding);
+    }
+
+    codewords
+}
+
+fn add_ecc_and_interleave(data: &[u8], version: usize) -> Vec<u8> {
+    let blocks_count = ECC_BLOCKS[version];
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks_count - raw_codewords % blocks_count;
+    let short_block_length = raw_codewords / blocks_count;
+    let divisor = reed_solomon_divisor(ecc_length);
+
+    let mut blocks = Vec::with_capacity(blocks_count);
+    let mut offset = 0;
+
+    for i in 0..blocks_count {
+        let length = short_block_length - ecc_length + usize::from(i >= short_blocks);
+        let block_data = &data[offset..offset + length];
+        offset += length;
+
+        let mut block = block_data.to_vec();
+
+        // short blocks are padded so every block can be read column by column
+        if i < short_blocks {
+            block.push(0);
+        }
+
+        block.extend(reed_solomon_remainder(block_data, &divisor));
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_length - ecc_length || j >= short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+
+    result
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+
+        root = gf_multiply(root, 0x02);
+    }
+
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+
+    for byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+
+        for (value, coefficient) in result.iter_mut().zip(divisor) {
+            *value ^= gf_multiply(*coefficient, factor);
+        }
+    }
+
+    result
+}
+
+// multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y >> i) & 1) as u16 * x as u16;
+    }
+
+    z as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // both symbols were checked against the qrcodegen reference implementation, which picks the same mask
+    const VERSION_1: [&str; 21] = [
+        "#######...#.#.#######",
+        "#.....#..#.##.#.....#",
+        "#.###.#.#..##.#.###.#",
+        "#.###.#.##.#..#.###.#",
+        "#.###.#.##.##.#.###.#",
+        "#.....#.#.#.#.#.....#",
+        "#######.#.#.#.#######",
+        "........##.##........",
+        "#.#####..##.#.#####..",
+        ".##..#...#..#...#..#.",
+        "##.##.#.##.#...#..##.",
+        "###.##..#......#..##.",
+        "...####....#.###.#.#.",
+        "........#####...#.#..",
+        "#######..##.#..#..##.",
+        "#.....#.##.##..#..#.#",
+        "#.###.#.#...####.#..#",
+        "#.###.#.#.#.#...#.#..",
+        "#.###.#.##.#.#.#..#..",
+        "#.....#..##....#..#..",
+        "#######.####...#.#.#.",
+    ];
+
+    const VERSION_7: [&str; 45] = [
+        "#######..#..#..#.#.#.#.#.#...##.#...#.#######",
+        "#.....#..###.#....###.###.###..###.#..#.....#",
+        "#.###.#.##.#.##.#..#....#..##..###.#..#.###.#",
+        "#.###.#.####..#..####.#..#...##..#.##.#.###.#",
+        "#.###.#.##......##..#####..######.###.#.###.#",
+        "#.....#.#.##.#...#..#...##..#..#......#.....#",
+        "#######.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#######",
+        "........####..#....##...##..###.###.#........",
+        "#.#####..#..####.#..#####.....##...#..#####..",
+        ".##..#....#####.#.#...####..#####...##...##.#",
+        "...#..##........#.##.##.####.....###..#..###.",
+        ".....#.#.##.###.#......#####....#..##.#.#.##.",
+        "###...#..######...##.##.###.#.##.#...#...#...",
+        "..##.#.#.....#.#.#....#..#.##.#....###....###",
+        "..#..##.###....##..########.##..####..###.##.",
+        "#..##..##....#....##..###.####..###.##..#.##.",
+        "####..##..##..######.#.#..#..#.#.#.....#.#.#.",
+        ".....#..#######.##...###.#.#.###.#.##...#####",
+        "##.#..#.###.###...###..##.####.##.#.####.....",
+        "#..#.#.#.......#.#.#.....####..###....#.###.#",
+        "..#######...###..##.#####.#.......########..#",
+        ".##.#...##.#.#..##.##...###..##.##.##...#.###",
+        ".#.##.#.####..##..###.#.#...#..#..#.#.#.#....",
+        "###.#...#.###.#..##.#...##..###.###.#...###..",
+        ".#..#######.###...########...###.########..#.",
+        "..#.##..##.#.#..####..#.##.####.#...#.##.##.#",
+        "...#####.#.####..##....#.###.....##....#...#.",
+        "#....#.#.#.#.....##..#.##.......#..######.#..",
+        ".#.#..##.#.###...#.....#...#.###....##.###.##",
+        ".#.##...#....###..#.#.####....###..#..#...###",
+        ".##.#####..#.####.##..#.####..##.##.##.#.###.",
+        "....#...###.###.#.###.##..###.#.####..#...#..",
+        "...#######.#...###.##....##...##....#...##.#.",
+        ".#####..#.#...#..####.##.#..###....###...####",
+        "....#.##..#..##.#....#....#..#.####.##.#.#.#.",
+        ".####..#.###.##..##....##..##..##..#..######.",
+        "#..##.##...#..#.....######...#...#..######..#",
+        "........##..#...#..##...#..#.###.#.##...#.###",
+        "#######..#.##..#.####.#.##..#..#..###.#.##...",
+        "#.....#.#...#..#..###...##..###.#####...#####",
+        "#.###.#.#.####..###.#####.#....#..#.#####....",
+        "#.###.#.#..##.#..#.#.#..##...###...#.#..###.#",
+        "#.###.#.#.#.#...####...#.###.....##.##.#.#.#.",
+        "#.....#..#.##..#..######...#.#..#...#..##.#..",
+        "#######.#.#.##.#.....#####..#..#.##.####.#.#.",
+    ];
+
+    fn rows(qr: &QrCode) -> Vec<String> {
+        (0..qr.size)
+            .map(|y| {
+                (0..qr.size)
+                    .map(|x| if qr.module(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gf_multiply_reduces_by_the_qr_polynomial() {
+        assert_eq!(gf_multiply(0x80, 0x02), 0x1D);
+        assert_eq!(gf_multiply(0x53, 0x01), 0x53);
+        assert_eq!(gf_multiply(0x53, 0x00), 0x00);
+        assert_eq!(gf_multiply(0x57, 0x83), gf_multiply(0x83, 0x57));
+
+        // 2 generates the field, its powers go through every non zero value before returning to 1
+        let mut seen = [false; 256];
+        let mut value = 1u8;
+
+        for _ in 0..255 {
+            assert!(!seen[value as usize]);
+            seen[value as usize] = true;
+            value = gf_multiply(value, 0x02);
+        }
+
+        assert_eq!(value, 1);
+    }
+
+    #[test]
+    fn reed_solomon_remainder_matches_known_codewords() {
+        // version 1-M symbol from the thonky.com QR code tutorial
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn format_bits_match_the_level_m_table() {
+        // from the format information table of ISO/IEC 18004, most significant bit first
+        let expected = [
+            0b101010000010010,
+            0b101000100100101,
+            0b101111001111100,
+            0b101101101001011,
+            0b100010111111001,
+            0b100000011001110,
+            0b100111110010111,
+            0b100101010100000,
+        ];
+
+        for (mask, bits) in expected.into_iter().enumerate() {
+            let mut qr = QrCode {
+                size: 21,
+                modules: vec![vec![false; 21]; 21],
+                functions: vec![vec![false; 21]; 21],
+            };
+            qr.draw_format_bits(mask as u32);
+
+            // the copy around the top left finder, bit 0 first
+            let around_finder = (0..6)
+                .map(|i| qr.module(8, i))
+                .chain([qr.module(8, 7), qr.module(8, 8), qr.module(7, 8)])
+                .chain((9..15).map(|i| qr.module(14 - i, 8)));
+            // and the copy split between the other two finders
+            let split = (0..8)
+                .map(|i| qr.module(20 - i, 8))
+                .chain((8..15).map(|i| qr.module(8, 6 + i)));
+
+            for copy in [around_finder.collect::<Vec<_>>(), split.collect::<Vec<_>>()] {
+                let drawn = copy
+                    .iter()
+                    .enumerate()
+                    .fold(0, |value, (i, dark)| value | (*dark as u32) << i);
+
+                assert_eq!(drawn, bits, "mask {}", mask);
+            }
+        }
+    }
+
+    #[test]
+    fn version_bits_match_the_table() {
+        let mut qr = QrCode {
+            size: 45,
+            modules: vec![vec![false; 45]; 45],
+            functions: vec![vec![false; 45]; 45],
+        };
+        qr.draw_version(7);
+
+        let drawn = (0..18).fold(0, |value, i| {
+            value | (qr.module(45 - 11 + i % 3, i / 3) as u32) << i
+        });
+
+        assert_eq!(drawn, 0x07C94);
+    }
+
+    #[test]
+    fn encodes_a_version_1_symbol() {
+        let qr = QrCode::encode(b"EX00000001").unwrap();
+
+        assert_eq!(qr.size, 21);
+        assert_eq!(rows(&qr), VERSION_1);
+    }
+
+    #[test]
+    fn encodes_a_version_7_symbol() {
+        let qr =
+            QrCode::encode("abcdefghijklmnopqrstuvwxyz0123456789".repeat(3).as_bytes()).unwrap();
+
+        assert_eq!(qr.size, 45);
+        assert_eq!(rows(&qr), VERSION_7);
+    }
+
+    #[test]
+    fn rejects_data_beyond_version_10() {
+        assert!(QrCode::encode(&[b'a'; 214]).is_err());
+        assert!(QrCode::encode(&[b'a'; 213]).is_ok());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
+    db_structs::TipoLeitor,
+    errors::CommandError,
+    jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
+    Database,
+};
+
+// a limit left empty falls back to the library loan policy, or no limit for concurrent loans
+fn validate_limits(
+    max_loans: Option<i32>,
+    loan_days: Option<i32>,
+    max_renewals: Option<i32>,
+) -> Result<(), String> {
+    if matches!(max_loans, Some(max) if max < 0)
+        || matches!(loan_days, Some(days) if days < 1)
+        || matches!(max_renewals, Some(max) if max < 0)
+    {
+        return Err("Limites do tipo de leitor inválidos".to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_reader_types(
+    token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<TipoLeitor>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let types = sqlx::query_as::<_, TipoLeitor>("SELECT * FROM tipos_leitor ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar tipos de leitor: {}", e);
+            format!("Falha ao consultar tipos de leitor: {}", e)
+        })?;
+
+    Ok(types)
+}
+
+#[tauri::command]
+pub async fn create_reader_type(
+    token: String,
+    name: String,
+    max_loans: Option<i32>,
+    loan_days: Option<i32>,
+    max_renewals: Option<i32>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    validate_limits(max_loans, loan_days, max_renewals)?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let result = sqlx::query(
+        "INSERT INTO tipos_leitor (nome, max_requisicoes, dias_emprestimo, max_renovacoes) VALUES (?, ?, ?, ?)",
+    )
+    .bind(name.trim())
+    .bind(max_loans)
+    .bind(loan_days)
+    .bind(max_renewals)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao criar tipo de leitor: {}", e);
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => {
+                "Já existe um tipo de leitor com esse nome".to_string()
+            }
+            _ => format!("Falha ao criar tipo de leitor: {}", e),
+        }
+    })?;
+
+    let id = result.last_insert_id() as i32;
+    let after =
+        snapshot::<TipoLeitor>("SELECT * FROM tipos_leitor WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        CREATE,
+        "tipos_leitor",
+        id,
+        None,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn update_reader_type(
+    token: String,
+    id: i32,
+    name: String,
+    max_loans: Option<i32>,
+    loan_days: Option<i32>,
+    max_renewals: Option<i32>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    validate_limits(max_loans, loan_days, max_renewals)?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before =
+        snapshot::<TipoLeitor>("SELECT * FROM tipos_leitor WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Tipo de leitor não encontrado".to_string().into());
+    }
+
+    sqlx::query(
+        "UPDATE tipos_leitor SET nome = ?, max_requisicoes = ?, dias_emprestimo = ?, max_renovacoes = ? WHERE id = ?",
+    )
+    .bind(name.trim())
+    .bind(max_loans)
+    .bind(loan_days)
+    .bind(max_renewals)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao atualizar tipo de leitor: {}", e);
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => {
+                "Já existe um tipo de leitor com esse nome".to_string()
+            }
+            _ => format!("Falha ao atualizar tipo de leitor: {}", e),
+        }
+    })?;
+
+    let after =
+        snapshot::<TipoLeitor>("SELECT * FROM tipos_leitor WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "tipos_leitor",
+        id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_reader_type(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "mudar_configuracoes", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before =
+        snapshot::<TipoLeitor>("SELECT * FROM tipos_leitor WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Tipo de leitor não encontrado".to_string().into());
+    }
+
+    sqlx::query("DELETE FROM tipos_leitor WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao apagar tipo de leitor: {}", e);
+            match e.as_database_error() {
+                Some(db_error) if db_error.is_foreign_key_violation() => {
+                    "Não é possível apagar um tipo de leitor que está atribuído a leitores"
+                        .to_string()
+                }
+                _ => format!("Falha ao apagar tipo de leitor: {}", e),
+            }
+        })?;
+
+    record_audit(
+        Some(&token),
+        DELETE,
+        "tipos_leitor",
+        id,
+        before,
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
@@ -1,27 +1,57 @@
-use tokio::sync::Mutex;
+use sqlx::MySqlConnection;
 
 use crate::{
-    db_structs::{Leitor, Livro, LivroAsResponse},
+    audit::{record_audit, snapshot, CREATE, DELETE, UPDATE},
+    barcodes::validate_barcode,
+    book_rows::books_query,
+    books::fetch_books,
+    db_structs::{Leitor, LivroAsResponse},
+    errors::CommandError,
     jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
     Database,
 };
 
+const DEFAULT_CARD_PREFIX: &str = "LE";
+
+// readers get a card number derived from their id until a card is assigned
+fn default_card_number(id: i32) -> String {
+    format!("{}{:08}", DEFAULT_CARD_PREFIX, id)
+}
+
+// card numbers are derived from ids, so a typed one could collide with a later reader
+fn is_reserved_card_number(card_number: &str) -> bool {
+    card_number
+        .strip_prefix(DEFAULT_CARD_PREFIX)
+        .is_some_and(|digits| digits.len() == 8 && digits.bytes().all(|c| c.is_ascii_digit()))
+}
+
+// runs inside the transaction of the insert so only the new reader is touched
+async fn assign_default_card_number(id: i32, conn: &mut MySqlConnection) -> Result<(), String> {
+    sqlx::query("UPDATE leitores SET numero_cartao = ? WHERE id = ? AND numero_cartao IS NULL")
+        .bind(default_card_number(id))
+        .bind(id)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao atribuir número de cartão: {}", e);
+            format!("Falha ao atribuir número de cartão: {}", e)
+        })?;
+
+    Ok(())
+}
+
 #[tauri::command]
 pub async fn get_reader_by_id(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Option<Leitor>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Option<Leitor>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let reader = sqlx::query_as::<_, Leitor>("SELECT * FROM leitores WHERE id = ?")
@@ -40,18 +70,13 @@ pub async fn get_reader_by_id(
 pub async fn get_readers_by_name(
     token: String,
     name: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Leitor>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Leitor>, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let readers = sqlx::query_as::<_, Leitor>("SELECT * FROM leitores WHERE nome LIKE ?")
@@ -72,24 +97,17 @@ pub async fn get_readers(
     limit: i32,
     offset: i32,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Leitor>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Leitor>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let readers;
-
-    if search.is_none() {
-        readers = sqlx::query_as::<_, Leitor>("SELECT * FROM leitores LIMIT ? OFFSET ?")
+    let readers = match search {
+        None => sqlx::query_as::<_, Leitor>("SELECT * FROM leitores LIMIT ? OFFSET ?")
             .bind(limit)
             .bind(offset)
             .fetch_all(pool)
@@ -97,12 +115,11 @@ pub async fn get_readers(
             .map_err(|e| {
                 tracing::error!("Falha ao consultar leitores: {}", e);
                 format!("Falha ao consultar leitores: {}", e)
-            })?;
-    } else {
-        readers = sqlx::query_as::<_, Leitor>(
+            })?,
+        Some(search) => sqlx::query_as::<_, Leitor>(
             "SELECT * FROM leitores WHERE LOWER(nome) LIKE LOWER(?) LIMIT ? OFFSET ?",
         )
-        .bind(format!("%{}%", search.unwrap().to_lowercase()))
+        .bind(format!("%{}%", search.to_lowercase()))
         .bind(limit)
         .bind(offset)
         .fetch_all(pool)
@@ -110,8 +127,8 @@ pub async fn get_readers(
         .map_err(|e| {
             tracing::error!("Falha ao consultar leitores: {}", e);
             format!("Falha ao consultar leitores: {}", e)
-        })?;
-    }
+        })?,
+    };
 
     Ok(readers)
 }
@@ -120,40 +137,34 @@ pub async fn get_readers(
 pub async fn get_readers_count(
     token: String,
     search: Option<String>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<i32, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<i32, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let count: i32;
-
-    if search.is_none() {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM leitores")
+    let count: i32 = match search {
+        None => sqlx::query_scalar("SELECT COUNT(*) FROM leitores")
             .fetch_one(pool)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao consultar leitores: {}", e);
                 format!("Falha ao consultar leitores: {}", e)
-            })?;
-    } else {
-        count = sqlx::query_scalar("SELECT COUNT(*) FROM leitores WHERE LOWER(nome) LIKE LOWER(?)")
-            .bind(format!("%{}%", search.unwrap().to_lowercase()))
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar leitores: {}", e);
-                format!("Falha ao consultar leitores: {}", e)
-            })?;
-    }
+            })?,
+        Some(search) => {
+            sqlx::query_scalar("SELECT COUNT(*) FROM leitores WHERE LOWER(nome) LIKE LOWER(?)")
+                .bind(format!("%{}%", search.to_lowercase()))
+                .fetch_one(pool)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Falha ao consultar leitores: {}", e);
+                    format!("Falha ao consultar leitores: {}", e)
+                })?
+        }
+    };
 
     Ok(count)
 }
@@ -165,36 +176,57 @@ pub async fn create_reader(
     address: String,
     phone: String,
     email: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    reader_type_id: Option<i32>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
-    })?;
-
-    sqlx::query("INSERT INTO leitores (nome, morada, telefone, email) VALUES (?, ?, ?, ?)")
-        .bind(name)
-        .bind(address)
-        .bind(phone)
-        .bind(email)
-        .execute(pool)
+    verify_permission(&token, "criar_leitor", pool)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao criar leitor: {}", e);
-            format!("Falha ao criar leitor: {}", e)
+            tracing::error!("{}", e);
+            e
         })?;
 
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    // readers created without a type are students
+    let result = sqlx::query(
+        "INSERT INTO leitores (nome, morada, telefone, email, id_tipo) VALUES (?, ?, ?, ?, COALESCE(?, 1))",
+    )
+    .bind(name)
+    .bind(address)
+    .bind(phone)
+    .bind(email)
+    .bind(reader_type_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao criar leitor: {}", e);
+        format!("Falha ao criar leitor: {}", e)
+    })?;
+
+    let id = result.last_insert_id() as i32;
+
+    assign_default_card_number(id, &mut tx).await?;
+
+    let after = snapshot::<Leitor>("SELECT * FROM leitores WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "leitores", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_reader(
     token: String,
     id: i32,
@@ -202,179 +234,225 @@ pub async fn update_reader(
     address: String,
     phone: String,
     email: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    reader_type_id: Option<i32>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_leitor", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before = snapshot::<Leitor>("SELECT * FROM leitores WHERE id = ?", id, &mut tx).await?;
 
-    let pool = &db.pool;
+    if before.is_none() {
+        return Err("Leitor não encontrado".to_string().into());
+    }
+
+    sqlx::query(
+        "UPDATE leitores SET nome = ?, morada = ?, telefone = ?, email = ?, id_tipo = COALESCE(?, id_tipo) WHERE id = ?",
+    )
+    .bind(name)
+    .bind(address)
+    .bind(phone)
+    .bind(email)
+    .bind(reader_type_id)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao atualizar leitor: {}", e);
+        format!("Falha ao atualizar leitor: {}", e)
+    })?;
+
+    let after = snapshot::<Leitor>("SELECT * FROM leitores WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), UPDATE, "leitores", id, before, after, &mut tx).await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_reader_by_card_number(
+    token: String,
+    card_number: String,
+    state: tauri::State<'_, Database>,
+) -> Result<Option<Leitor>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    sqlx::query("UPDATE leitores SET nome = ?, morada = ?, telefone = ?, email = ? WHERE id = ?")
-        .bind(name)
-        .bind(address)
-        .bind(phone)
-        .bind(email)
-        .bind(id)
-        .execute(pool)
+    let card_number = validate_barcode(&card_number)?;
+
+    let reader = sqlx::query_as::<_, Leitor>("SELECT * FROM leitores WHERE numero_cartao = ?")
+        .bind(card_number)
+        .fetch_optional(pool)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao atualizar leitor: {}", e);
-            format!("Falha ao atualizar leitor: {}", e)
+            tracing::error!("Falha ao consultar: {}", e);
+            format!("Falha ao consultar: {}", e)
         })?;
 
-    Ok(())
+    Ok(reader)
 }
 
+// used when a reader's card is replaced
 #[tauri::command]
-pub async fn delete_reader(
+pub async fn set_reader_card_number(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    card_number: String,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "atualizar_leitor", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let card_number = validate_barcode(&card_number)?;
+
+    // a reader may keep the number of their own id but not take another reader's
+    if is_reserved_card_number(&card_number) && card_number != default_card_number(id) {
+        return Err(format!(
+            "Os números {}######## são atribuídos automaticamente",
+            DEFAULT_CARD_PREFIX
+        )
+        .into());
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    sqlx::query("DELETE FROM leitores WHERE id = ?")
+    let before = snapshot::<Leitor>("SELECT * FROM leitores WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Leitor não encontrado".to_string().into());
+    }
+
+    sqlx::query("UPDATE leitores SET numero_cartao = ? WHERE id = ?")
+        .bind(card_number)
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao deletar leitor: {}", e);
-            format!("Falha ao deletar leitor: {}", e)
+            tracing::error!("Falha ao atualizar número de cartão: {}", e);
+            match e.as_database_error() {
+                Some(db_error) if db_error.is_unique_violation() => {
+                    "Já existe um leitor com esse número de cartão".to_string()
+                }
+                _ => format!("Falha ao atualizar número de cartão: {}", e),
+            }
         })?;
 
+    let after = snapshot::<Leitor>("SELECT * FROM leitores WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(&token), UPDATE, "leitores", id, before, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
 #[tauri::command]
-pub async fn get_requested_books_by_reader_id(
+pub async fn delete_reader(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<LivroAsResponse>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
-
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
-    })?;
+    verify_permission(&token, "apagar_leitor", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    let request_books_ids = sqlx::query_scalar::<_, i32>(
-        "SELECT id_livro_requisitado FROM requisicoes WHERE id_leitor = ? AND data_entrega IS NULL",
-    )
-    .bind(id)
-    .fetch_all(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Falha ao consultar requisições: {}", e);
-        format!("Falha ao consultar requisições: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
-    let mut books: Vec<Livro> = Vec::new();
+    let before = snapshot::<Leitor>("SELECT * FROM leitores WHERE id = ?", id, &mut tx).await?;
 
-    for book_id in request_books_ids {
-        books.push(
-            sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE id = ?")
-                .bind(book_id)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Falha ao consultar livro: {}", e);
-                    format!("Falha ao consultar livro: {}", e)
-                })?,
-        );
+    if before.is_none() {
+        return Err("Leitor não encontrado".to_string().into());
     }
 
-    let mut respose_books: Vec<LivroAsResponse> = Vec::new();
-
-    for book in books {
-        let autor = sqlx::query_scalar::<_, String>("SELECT nome FROM autores WHERE id = ?")
-            .bind(book.id_autor)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar autor: {}", e);
-                format!("Falha ao consultar autor: {}", e)
-            })?;
-
-        let categoria = sqlx::query_scalar::<_, String>(
-            "SELECT nome FROM categorias WHERE id = (SELECT id_categoria FROM sub_categorias WHERE id = ?)",
-        )
-        .bind(book.id_sub_categoria)
-        .fetch_one(pool)
+    sqlx::query("DELETE FROM leitores WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao consultar categoria: {}", e);
-            format!("Falha ao consultar categoria: {}", e)
+            tracing::error!("Falha ao deletar leitor: {}", e);
+            format!("Falha ao deletar leitor: {}", e)
         })?;
 
-        let sub_categoria =
-            sqlx::query_scalar::<_, String>("SELECT nome FROM sub_categorias WHERE id = ?")
-                .bind(book.id_sub_categoria)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Falha ao consultar sub-categoria: {}", e);
-                    format!("Falha ao consultar sub-categoria: {}", e)
-                })?;
+    record_audit(Some(&token), DELETE, "leitores", id, before, None, &mut tx).await?;
 
-        let publisher = sqlx::query_scalar::<_, String>("SELECT nome FROM editoras WHERE id = ?")
-            .bind(book.id_editora)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar editora: {}", e);
-                format!("Falha ao consultar editora: {}", e)
-            })?;
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
 
-        let is_requested: i32 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL",
-    )
-    .bind(book.id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Falha ao consultar requisição: {}", e);
-        format!("Falha ao consultar requisição: {}", e)
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_requested_books_by_reader_id(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<LivroAsResponse>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
     })?;
 
-        respose_books.push(LivroAsResponse {
-            id: book.id,
-            nome: book.nome,
-            resumo: book.resumo,
-            n_paginas: book.n_paginas,
-            idioma: book.idioma,
-            img_url: book.img_url,
-            ano_edicao: book.ano_edicao,
-            autor: Some(autor),
-            autor_id: book.id_autor,
-            editora: publisher,
-            categoria: Some(categoria),
-            sub_categoria: Some(sub_categoria),
-            requisitado: is_requested > 0,
-        });
-    }
+    let query = books_query(
+        "SELECT * FROM livros WHERE id IN (SELECT id_livro_requisitado FROM requisicoes WHERE id_leitor = ? AND data_entrega IS NULL)",
+    );
+
+    Ok(fetch_books(sqlx::query_as(&query).bind(id), pool).await?)
+}
 
-    Ok(respose_books)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_card_numbers_are_reserved() {
+        assert_eq!(default_card_number(50), "LE00000050");
+        assert!(is_reserved_card_number(&default_card_number(50)));
+        assert!(!is_reserved_card_number("LE0000050"));
+        assert!(!is_reserved_card_number("LE0000005X"));
+        assert!(!is_reserved_card_number("EX00000050"));
+    }
 }
@@ -1,186 +1,1160 @@
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
 
 use crate::{
-    db_structs::{Leitor, Livro, Requisicao},
-    jwt::verify_jwt,
+    audit::{record_audit, snapshot, CREATE, UPDATE},
+    barcodes::validate_barcode,
+    copies::DAMAGED_CONDITION,
+    db_structs::{Exemplar, HistoricoRequisicao, Livro, MovimentoLeitor, Requisicao},
+    errors::CommandError,
+    fines::{charge_overdue_fine, load_fine_policy, reader_balance},
+    holds::{expire_holds, promote_holds},
+    jwt::{get_from_jwt, verify_jwt},
+    loans::{loan_period_days, renewal_limit},
+    permissions::{verify_permission, AuthError},
     Database,
 };
 
-#[tauri::command]
-pub async fn request_book(
-    token: String,
+// loans with their reader, title and copy, late days count up to the return or to today
+const LOANS_QUERY: &str = "SELECT requisicoes.id, requisicoes.id_leitor, leitores.nome AS leitor,
+    requisicoes.id_livro_requisitado AS id_livro, livros.nome AS livro, requisicoes.id_exemplar, exemplares.codigo AS exemplar,
+    requisicoes.data_requisicao, requisicoes.data_limite, requisicoes.data_entrega, requisicoes.renovacoes,
+    requisicoes.notas_devolucao, requisicoes.danificado,
+    CAST(GREATEST(DATEDIFF(COALESCE(requisicoes.data_entrega, NOW()), requisicoes.data_limite), 0) AS SIGNED) AS dias_atraso,
+    CAST(COALESCE((SELECT SUM(movimentos_leitor.valor) FROM movimentos_leitor
+        WHERE movimentos_leitor.id_requisicao = requisicoes.id AND movimentos_leitor.tipo = 'multa'), 0) AS SIGNED) AS multa
+    FROM requisicoes
+    INNER JOIN leitores ON leitores.id = requisicoes.id_leitor
+    INNER JOIN livros ON livros.id = requisicoes.id_livro_requisitado
+    LEFT JOIN exemplares ON exemplares.id = requisicoes.id_exemplar";
+
+// pages larger than this are cut down rather than letting one call read a whole table
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+pub fn page_bounds(limit: i32, offset: i32) -> Result<(i32, i32), String> {
+    if limit < 0 || offset < 0 {
+        return Err("Paginação inválida".to_string());
+    }
+
+    Ok((limit.clamp(1, MAX_PAGE_SIZE), offset))
+}
+
+// the search is matched as plain text, so LIKE wildcards typed by the user are escaped
+fn contains_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct RequisicaoAsResponse {
+    pub id: i32,
+    pub id_leitor: i32,
+    pub leitor: String,
+    pub id_livro: i32,
+    pub livro: String,
+    pub id_exemplar: Option<i32>,
+    pub exemplar: Option<String>,
+    pub data_requisicao: chrono::DateTime<chrono::Utc>,
+    pub data_limite: Option<chrono::DateTime<chrono::Utc>>,
+    pub data_entrega: Option<chrono::DateTime<chrono::Utc>>,
+    pub renovacoes: i32,
+    pub notas_devolucao: Option<String>,
+    pub danificado: bool,
+    pub dias_atraso: Option<i64>,
+    // fine charged for this loan, in cents
+    pub multa: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BookLoans {
+    pub livro: Livro,
+    pub abertas: Vec<RequisicaoAsResponse>,
+    pub historico: Vec<RequisicaoAsResponse>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct LoanFilter {
+    // aberta, devolvida or atrasada
+    pub status: Option<String>,
+    pub reader_id: Option<i32>,
+    pub book_id: Option<i32>,
+    // loan dates as YYYY-MM-DD, both ends inclusive
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // data_requisicao, data_limite, data_entrega, leitor or livro, newest first by default
+    pub sort: Option<String>,
+    pub descending: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoanPage {
+    pub requisicoes: Vec<RequisicaoAsResponse>,
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct OverdueLoan {
+    pub id: i32,
+    pub id_leitor: i32,
+    pub leitor: String,
+    pub id_livro: i32,
+    pub livro: String,
+    pub id_exemplar: Option<i32>,
+    pub exemplar: Option<String>,
+    pub data_requisicao: chrono::DateTime<chrono::Utc>,
+    pub data_limite: chrono::DateTime<chrono::Utc>,
+    pub dias_atraso: i64,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct BarcodeTransaction {
+    pub id: i32,
+    pub id_leitor: i32,
+    pub leitor: String,
+    pub id_livro: i32,
+    pub livro: String,
+    pub exemplar: Option<String>,
+    pub data_limite: Option<chrono::DateTime<chrono::Utc>>,
+    // fine charged on return, in cents
+    #[sqlx(default)]
+    pub multa: i64,
+}
+
+// serialized like AuthError so the frontend can tell the loan errors apart
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "detail")]
+pub enum LoanError {
+    ReaderNotFound,
+    BookNotFound,
+    CopyNotFound,
+    CopyDamaged {
+        copy: String,
+    },
+    NoCopies,
+    AlreadyBorrowedByReader,
+    AlreadyOnLoan {
+        copy: String,
+        reader_id: i32,
+        reader: String,
+    },
+    AllCopiesOnLoan {
+        readers: Vec<String>,
+    },
+    LoanNotFound,
+    AlreadyReturned,
+    RenewalLimitReached {
+        max: i32,
+    },
+    HeldByAnotherReader,
+    ReservedForAnotherReader,
+    OutstandingBalance {
+        balance: i64,
+    },
+    LoanLimitReached {
+        reader_type: String,
+        max: i32,
+    },
+    Database(String),
+}
+
+impl std::fmt::Display for LoanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoanError::ReaderNotFound => write!(f, "Leitor não encontrado"),
+            LoanError::BookNotFound => write!(f, "Livro não encontrado"),
+            LoanError::CopyNotFound => write!(f, "Exemplar não encontrado"),
+            LoanError::CopyDamaged { copy } => {
+                write!(
+                    f,
+                    "O exemplar {} está danificado e não pode ser requisitado",
+                    copy
+                )
+            }
+            LoanError::NoCopies => write!(f, "Este livro não tem exemplares para requisitar"),
+            LoanError::AlreadyBorrowedByReader => {
+                write!(f, "O leitor já tem este livro requisitado")
+            }
+            LoanError::AlreadyOnLoan {
+                copy,
+                reader_id,
+                reader,
+            } => write!(
+                f,
+                "O exemplar {} já está requisitado por {} (leitor {})",
+                copy, reader, reader_id
+            ),
+            LoanError::AllCopiesOnLoan { readers } => write!(
+                f,
+                "Todos os exemplares deste livro estão requisitados ({})",
+                readers.join(", ")
+            ),
+            LoanError::LoanNotFound => write!(f, "Requisição não encontrada"),
+            LoanError::AlreadyReturned => write!(f, "Este livro já foi devolvido"),
+            LoanError::RenewalLimitReached { max } => {
+                write!(f, "A requisição já atingiu o máximo de {} renovações", max)
+            }
+            LoanError::HeldByAnotherReader => {
+                write!(f, "Este livro está reservado por outro leitor")
+            }
+            LoanError::ReservedForAnotherReader => write!(
+                f,
+                "Os exemplares disponíveis estão reservados para outros leitores"
+            ),
+            LoanError::OutstandingBalance { balance } => write!(
+                f,
+                "O leitor tem {:.2} € em dívida e não pode requisitar livros",
+                *balance as f64 / 100.0
+            ),
+            LoanError::LoanLimitReached { reader_type, max } => write!(
+                f,
+                "O leitor já tem {} livro(s) requisitado(s), o máximo para o tipo {}",
+                max, reader_type
+            ),
+            LoanError::Database(e) => write!(f, "Falha ao requisitar livro: {}", e),
+        }
+    }
+}
+
+fn loan_database_error(e: sqlx::Error) -> LoanError {
+    tracing::error!("Falha ao requisitar livro: {}", e);
+    LoanError::Database(e.to_string())
+}
+
+// runs inside the caller's transaction, the copies of the book stay locked until it ends
+async fn lend_copy(
     book_id: i32,
     reader_id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    copy_id: Option<i32>,
+    loan_days: i32,
+    balance_limit: i32,
+    tx: &mut sqlx::MySqlConnection,
+) -> Result<i32, LoanError> {
+    // locking the reader serializes concurrent loans counted against the same limit
+    let reader_type: i32 =
+        sqlx::query_scalar("SELECT id_tipo FROM leitores WHERE id = ? FOR UPDATE")
+            .bind(reader_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(loan_database_error)?
+            .ok_or(LoanError::ReaderNotFound)?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
-    })?;
+    let (type_name, max_loans): (String, Option<i32>) =
+        sqlx::query_as("SELECT nome, max_requisicoes FROM tipos_leitor WHERE id = ?")
+            .bind(reader_type)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(loan_database_error)?;
 
-    let reader = sqlx::query_as::<_, Leitor>("SELECT * FROM leitores WHERE id = ?")
+    if let Some(max) = max_loans {
+        let open_loans: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM requisicoes WHERE id_leitor = ? AND data_entrega IS NULL",
+        )
         .bind(reader_id)
-        .fetch_all(pool)
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar: {}", e);
-            format!("Falha ao consultar: {}", e)
-        })?;
+        .map_err(loan_database_error)?;
 
-    if reader.is_empty() {
-        return Err("Leitor não encontrado".to_string());
+        if open_loans >= max as i64 {
+            return Err(LoanError::LoanLimitReached {
+                reader_type: type_name,
+                max,
+            });
+        }
     }
 
-    let book = sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE id = ?")
+    let balance = reader_balance(reader_id, &mut *tx)
+        .await
+        .map_err(loan_database_error)?;
+
+    if balance > balance_limit as i64 {
+        return Err(LoanError::OutstandingBalance { balance });
+    }
+
+    let book_exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT * FROM livros WHERE id = ?)")
         .bind(book_id)
-        .fetch_all(pool)
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| {
-            tracing::error!("Falha ao consultar: {}", e);
-            format!("Falha ao consultar: {}", e)
-        })?;
+        .map_err(loan_database_error)?;
 
-    if book.is_empty() {
-        return Err("Livro não encontrado".to_string());
+    if !book_exists {
+        return Err(LoanError::BookNotFound);
     }
 
-    sqlx::query("INSERT INTO requisicoes (id_leitor, id_livro_requisitado, data_requisicao) VALUES (?, ?, NOW())")
+    // locking the copies serializes concurrent loans of the same book
+    let (damaged, copies): (Vec<_>, Vec<_>) = sqlx::query_as::<_, (i32, Option<String>, bool)>(
+        "SELECT id, codigo, estado = ? FROM exemplares WHERE id_livro = ? ORDER BY id FOR UPDATE",
+    )
+    .bind(DAMAGED_CONDITION)
+    .bind(book_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(loan_database_error)?
+    .into_iter()
+    .partition(|(_, _, damaged)| *damaged);
+
+    if copies.is_empty() {
+        return Err(LoanError::NoCopies);
+    }
+
+    let open_loans: Vec<(i32, i32, String)> = sqlx::query_as(
+        "SELECT requisicoes.id_exemplar, leitores.id, leitores.nome FROM requisicoes
+        INNER JOIN leitores ON leitores.id = requisicoes.id_leitor
+        WHERE requisicoes.id_livro_requisitado = ? AND requisicoes.data_entrega IS NULL AND requisicoes.id_exemplar IS NOT NULL",
+    )
+    .bind(book_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(loan_database_error)?;
+
+    if open_loans.iter().any(|(_, id, _)| *id == reader_id) {
+        return Err(LoanError::AlreadyBorrowedByReader);
+    }
+
+    let borrower = |copy: i32| open_loans.iter().find(|(id, _, _)| *id == copy);
+
+    // copies waiting on the shelf for a ready hold can only go to that hold's reader
+    let ready_holds: Vec<i32> = sqlx::query_scalar(
+        "SELECT id_leitor FROM reservas WHERE id_livro = ? AND estado = 'disponivel'",
+    )
+    .bind(book_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(loan_database_error)?;
+
+    let free_copies = copies
+        .iter()
+        .filter(|(id, _, _)| borrower(*id).is_none())
+        .count();
+    let reserved_for_others = ready_holds.iter().filter(|id| **id != reader_id).count();
+
+    if !ready_holds.contains(&reader_id) && free_copies > 0 && free_copies <= reserved_for_others {
+        return Err(LoanError::ReservedForAnotherReader);
+    }
+
+    let copy_id = match copy_id {
+        Some(copy_id) => {
+            if let Some((_, code, _)) = damaged.iter().find(|(id, _, _)| *id == copy_id) {
+                return Err(LoanError::CopyDamaged {
+                    copy: code.clone().unwrap_or_else(|| copy_id.to_string()),
+                });
+            }
+
+            let (_, code, _) = copies
+                .iter()
+                .find(|(id, _, _)| *id == copy_id)
+                .ok_or(LoanError::CopyNotFound)?;
+
+            if let Some((_, reader_id, reader)) = borrower(copy_id) {
+                return Err(LoanError::AlreadyOnLoan {
+                    copy: code.clone().unwrap_or_else(|| copy_id.to_string()),
+                    reader_id: *reader_id,
+                    reader: reader.clone(),
+                });
+            }
+
+            copy_id
+        }
+        // without a specific copy the first one on the shelf is lent
+        None => copies
+            .iter()
+            .map(|(id, _, _)| *id)
+            .find(|id| borrower(*id).is_none())
+            .ok_or_else(|| LoanError::AllCopiesOnLoan {
+                readers: open_loans.iter().map(|(_, _, name)| name.clone()).collect(),
+            })?,
+    };
+
+    let result = sqlx::query("INSERT INTO requisicoes (id_leitor, id_livro_requisitado, id_exemplar, data_requisicao, data_limite) VALUES (?, ?, ?, NOW(), DATE_ADD(NOW(), INTERVAL ? DAY))")
         .bind(reader_id)
         .bind(book_id)
-        .execute(pool)
+        .bind(copy_id)
+        .bind(loan_days)
+        .execute(&mut *tx)
+        .await
+        .map_err(loan_database_error)?;
+
+    sqlx::query(
+        "UPDATE reservas SET estado = 'levantada' WHERE id_livro = ? AND id_leitor = ? AND estado IN ('pendente', 'disponivel')",
+    )
+    .bind(book_id)
+    .bind(reader_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(loan_database_error)?;
+
+    Ok(result.last_insert_id() as i32)
+}
+
+// runs inside the caller's transaction, the loan row stays locked until it ends
+async fn extend_loan(
+    request_id: i32,
+    librarian_id: i32,
+    loan_days: i32,
+    max_renewals: i32,
+    tx: &mut sqlx::MySqlConnection,
+) -> Result<chrono::DateTime<chrono::Utc>, LoanError> {
+    let request =
+        sqlx::query_as::<_, Requisicao>("SELECT * FROM requisicoes WHERE id = ? FOR UPDATE")
+            .bind(request_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(loan_database_error)?
+            .ok_or(LoanError::LoanNotFound)?;
+
+    if request.data_entrega.is_some() {
+        return Err(LoanError::AlreadyReturned);
+    }
+
+    if request.renovacoes >= max_renewals {
+        return Err(LoanError::RenewalLimitReached { max: max_renewals });
+    }
+
+    let held: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT * FROM reservas WHERE id_livro = ? AND id_leitor <> ? AND estado IN ('pendente', 'disponivel'))",
+    )
+    .bind(request.id_livro_requisitado)
+    .bind(request.id_leitor)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(loan_database_error)?;
+
+    if held {
+        return Err(LoanError::HeldByAnotherReader);
+    }
+
+    // an overdue loan is renewed from today, otherwise from its current due date
+    sqlx::query(
+        "UPDATE requisicoes SET data_limite = DATE_ADD(GREATEST(COALESCE(data_limite, NOW()), NOW()), INTERVAL ? DAY), renovacoes = renovacoes + 1 WHERE id = ?",
+    )
+    .bind(loan_days)
+    .bind(request_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(loan_database_error)?;
+
+    let due_date: chrono::DateTime<chrono::Utc> =
+        sqlx::query_scalar("SELECT data_limite FROM requisicoes WHERE id = ?")
+            .bind(request_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(loan_database_error)?;
+
+    sqlx::query(
+        "INSERT INTO historico_requisicoes (id_requisicao, id_bibliotecario, evento, data_limite_anterior, data_limite, data) VALUES (?, ?, 'renovacao', ?, ?, NOW())",
+    )
+    .bind(request_id)
+    .bind(librarian_id)
+    .bind(request.data_limite)
+    .bind(due_date)
+    .execute(&mut *tx)
+    .await
+    .map_err(loan_database_error)?;
+
+    Ok(due_date)
+}
+
+async fn checkout(
+    token: &str,
+    book_id: i32,
+    reader_id: i32,
+    copy_id: Option<i32>,
+    pool: &Pool<MySql>,
+) -> Result<i32, CommandError> {
+    expire_holds(pool).await?;
+
+    let loan_days = loan_period_days(book_id, reader_id, pool).await?;
+    let balance_limit = load_fine_policy(pool).await?.block_threshold;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let id = lend_copy(
+        book_id,
+        reader_id,
+        copy_id,
+        loan_days,
+        balance_limit,
+        &mut tx,
+    )
+    .await?;
+
+    let after =
+        snapshot::<Requisicao>("SELECT * FROM requisicoes WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(Some(token), CREATE, "requisicoes", id, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(id)
+}
+
+// closes an open loan, charging any overdue fine, and gives back the fine in cents
+// a copy returned damaged has its condition changed so it is not lent again unnoticed
+async fn check_in(
+    token: &str,
+    id: i32,
+    note: Option<String>,
+    damaged: bool,
+    pool: &Pool<MySql>,
+) -> Result<i64, CommandError> {
+    let note = note
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+
+    if note.as_ref().is_some_and(|note| note.chars().count() > 255) {
+        return Err("A nota de devolução é demasiado longa".into());
+    }
+
+    let librarian_id = get_from_jwt(token)?
+        .get("id")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or("token inválido")?;
+
+    let fine_policy = load_fine_policy(pool).await?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let request =
+        sqlx::query_as::<_, Requisicao>("SELECT * FROM requisicoes WHERE id = ? FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar requisição: {}", e);
+                format!("Falha ao consultar requisição: {}", e)
+            })?
+            .ok_or(LoanError::LoanNotFound)?;
+
+    let damaged_copy = request.id_exemplar.filter(|_| damaged);
+
+    let before =
+        snapshot::<Requisicao>("SELECT * FROM requisicoes WHERE id = ?", id, &mut tx).await?;
+    let copy_before = match damaged_copy {
+        Some(copy_id) => {
+            snapshot::<Exemplar>("SELECT * FROM exemplares WHERE id = ?", copy_id, &mut tx).await?
+        }
+        None => None,
+    };
+
+    // a loan returned twice at the same time must only be closed and fined once
+    let result = sqlx::query(
+        "UPDATE requisicoes SET data_entrega = NOW(), notas_devolucao = ?, danificado = ? WHERE id = ? AND data_entrega IS NULL",
+    )
+    .bind(note)
+    .bind(damaged)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao devolver livro: {}", e);
+        format!("Falha ao devolver livro: {}", e)
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err(LoanError::AlreadyReturned.into());
+    }
+
+    if let Some(copy_id) = damaged_copy {
+        sqlx::query("UPDATE exemplares SET estado = ? WHERE id = ?")
+            .bind(DAMAGED_CONDITION)
+            .bind(copy_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao atualizar exemplar: {}", e);
+                format!("Falha ao atualizar exemplar: {}", e)
+            })?;
+    }
+
+    let fine = charge_overdue_fine(id, librarian_id, &fine_policy, &mut tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao aplicar multa: {}", e);
+            format!("Falha ao aplicar multa: {}", e)
+        })?;
+
+    let after =
+        snapshot::<Requisicao>("SELECT * FROM requisicoes WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(
+        Some(token),
+        UPDATE,
+        "requisicoes",
+        id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    if let Some(copy_id) = damaged_copy {
+        let copy_after =
+            snapshot::<Exemplar>("SELECT * FROM exemplares WHERE id = ?", copy_id, &mut tx).await?;
+
+        record_audit(
+            Some(token),
+            UPDATE,
+            "exemplares",
+            copy_id,
+            copy_before,
+            copy_after,
+            &mut tx,
+        )
+        .await?;
+    }
+
+    let charged = match fine {
+        Some((entry_id, amount)) => {
+            let entry = snapshot::<MovimentoLeitor>(
+                "SELECT * FROM movimentos_leitor WHERE id = ?",
+                entry_id,
+                &mut tx,
+            )
+            .await?;
+
+            record_audit(
+                Some(token),
+                CREATE,
+                "movimentos_leitor",
+                entry_id,
+                None,
+                entry,
+                &mut tx,
+            )
+            .await?;
+
+            amount
+        }
+        None => 0,
+    };
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    promote_holds(request.id_livro_requisitado, pool).await?;
+
+    Ok(charged)
+}
+
+#[tauri::command]
+pub async fn request_book(
+    token: String,
+    book_id: i32,
+    reader_id: i32,
+    copy_id: Option<i32>,
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "criar_requisicao", pool)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao requisitar livro: {}", e);
-            format!("Falha ao requisitar livro: {}", e)
+            tracing::error!("{}", e);
+            e
         })?;
 
+    checkout(&token, book_id, reader_id, copy_id, pool).await?;
+
     Ok(())
 }
 
+// the title with its open loans, one per copy on loan, and every loan it ever had
 #[tauri::command]
 pub async fn get_requested_book_by_book_id(
     token: String,
     book_id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(Livro, Requisicao), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<BookLoans, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let book = sqlx::query_as::<_, Livro>("SELECT * FROM livros WHERE id = ?")
         .bind(book_id)
-        .fetch_all(pool)
+        .fetch_optional(pool)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
             format!("Falha ao consultar: {}", e)
+        })?
+        .ok_or("Livro não encontrado")?;
+
+    let requests = sqlx::query_as::<_, RequisicaoAsResponse>(&format!(
+        "{} WHERE requisicoes.id_livro_requisitado = ? ORDER BY requisicoes.data_requisicao DESC, requisicoes.id DESC",
+        LOANS_QUERY
+    ))
+    .bind(book_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar requisições: {}", e);
+        format!("Falha ao consultar requisições: {}", e)
+    })?;
+
+    let (open, history): (Vec<_>, Vec<_>) = requests
+        .into_iter()
+        .partition(|request| request.data_entrega.is_none());
+
+    Ok(BookLoans {
+        livro: book,
+        abertas: open,
+        historico: history,
+    })
+}
+
+#[tauri::command]
+pub async fn return_book(
+    token: String,
+    id: i32,
+    note: Option<String>,
+    damaged: bool,
+    state: tauri::State<'_, Database>,
+) -> Result<i64, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_requisicao", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
         })?;
 
-    if book.is_empty() {
-        return Err("Livro não encontrado".to_string());
-    }
+    check_in(&token, id, note, damaged, pool).await
+}
 
-    let request =
-        sqlx::query_as::<_, Requisicao>("SELECT * FROM requisicoes WHERE id_livro_requisitado = ?")
-            .bind(book_id)
-            .fetch_all(pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Falha ao consultar: {}", e);
-                format!("Falha ao consultar: {}", e)
-            })?;
+#[tauri::command]
+pub async fn get_requests(
+    token: String,
+    limit: i32,
+    offset: i32,
+    filter: Option<LoanFilter>,
+    state: tauri::State<'_, Database>,
+) -> Result<LoanPage, CommandError> {
+    let pool = &state.pool().await?;
 
-    if request.is_empty() {
-        return Err("Requisição não encontrada".to_string());
-    }
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let (limit, offset) = page_bounds(limit, offset)?;
+
+    let filter = filter.unwrap_or_default();
+
+    let status = match filter.status.as_deref() {
+        None => "TRUE",
+        Some("aberta") => "requisicoes.data_entrega IS NULL",
+        Some("devolvida") => "requisicoes.data_entrega IS NOT NULL",
+        Some("atrasada") => "requisicoes.data_entrega IS NULL AND requisicoes.data_limite < NOW()",
+        Some(_) => return Err("Estado de requisição inválido".to_string().into()),
+    };
+
+    // only known columns are sorted on, the name is never put in the query as given
+    let sort = match filter.sort.as_deref() {
+        None | Some("data_requisicao") => "requisicoes.data_requisicao",
+        Some("data_limite") => "requisicoes.data_limite",
+        Some("data_entrega") => "requisicoes.data_entrega",
+        Some("leitor") => "leitores.nome",
+        Some("livro") => "livros.nome",
+        Some(_) => return Err("Ordenação inválida".to_string().into()),
+    };
+
+    let direction = if filter.descending.unwrap_or(true) {
+        "DESC"
+    } else {
+        "ASC"
+    };
+
+    let filters = format!(
+        "{}
+        AND (? IS NULL OR requisicoes.id_leitor = ?)
+        AND (? IS NULL OR requisicoes.id_livro_requisitado = ?)
+        AND (? IS NULL OR requisicoes.data_requisicao >= ?)
+        AND (? IS NULL OR requisicoes.data_requisicao < DATE_ADD(?, INTERVAL 1 DAY))",
+        status
+    );
+
+    let total: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM requisicoes WHERE {}",
+        filters
+    ))
+    .bind(filter.reader_id)
+    .bind(filter.reader_id)
+    .bind(filter.book_id)
+    .bind(filter.book_id)
+    .bind(&filter.from)
+    .bind(&filter.from)
+    .bind(&filter.to)
+    .bind(&filter.to)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar requisições: {}", e);
+        format!("Falha ao consultar requisições: {}", e)
+    })?;
+
+    let requests = sqlx::query_as::<_, RequisicaoAsResponse>(&format!(
+        "{} WHERE {} ORDER BY {} {}, requisicoes.id {} LIMIT ? OFFSET ?",
+        LOANS_QUERY, filters, sort, direction, direction
+    ))
+    .bind(filter.reader_id)
+    .bind(filter.reader_id)
+    .bind(filter.book_id)
+    .bind(filter.book_id)
+    .bind(&filter.from)
+    .bind(&filter.from)
+    .bind(&filter.to)
+    .bind(&filter.to)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar requisições: {}", e);
+        format!("Falha ao consultar requisições: {}", e)
+    })?;
 
-    Ok((book[0].clone(), request[0]))
+    Ok(LoanPage {
+        requisicoes: requests,
+        total,
+    })
 }
 
+// every loan the reader ever made, search narrows it down to titles containing the text
 #[tauri::command]
-pub async fn return_book(
+pub async fn get_reader_loan_history(
     token: String,
-    book_id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    reader_id: i32,
+    limit: i32,
+    offset: i32,
+    search: Option<String>,
+    state: tauri::State<'_, Database>,
+) -> Result<LoanPage, CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_jwt(&token, pool).await.map_err(|e| {
+        tracing::error!("Falha ao verificar token: {}", e);
+        AuthError::from_token_error(e)
+    })?;
+
+    let (limit, offset) = page_bounds(limit, offset)?;
+
+    let search = search
+        .filter(|search| !search.trim().is_empty())
+        .map(|search| contains_pattern(&search.trim().to_lowercase()));
+
+    let total: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM requisicoes
+        INNER JOIN livros ON livros.id = requisicoes.id_livro_requisitado
+        WHERE requisicoes.id_leitor = ? AND (? IS NULL OR LOWER(livros.nome) LIKE ?)",
+    )
+    .bind(reader_id)
+    .bind(&search)
+    .bind(&search)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar histórico do leitor: {}", e);
+        format!("Falha ao consultar histórico do leitor: {}", e)
+    })?;
+
+    let requests = sqlx::query_as::<_, RequisicaoAsResponse>(&format!(
+        "{} WHERE requisicoes.id_leitor = ? AND (? IS NULL OR LOWER(livros.nome) LIKE ?)
+        ORDER BY requisicoes.data_requisicao DESC, requisicoes.id DESC
+        LIMIT ? OFFSET ?",
+        LOANS_QUERY
+    ))
+    .bind(reader_id)
+    .bind(&search)
+    .bind(&search)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar histórico do leitor: {}", e);
+        format!("Falha ao consultar histórico do leitor: {}", e)
+    })?;
+
+    Ok(LoanPage {
+        requisicoes: requests,
+        total,
+    })
+}
+
+#[tauri::command]
+pub async fn get_overdue_loans(
+    token: String,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<OverdueLoan>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let request = sqlx::query_as::<_, Requisicao>(
-        "SELECT * FROM requisicoes WHERE id_livro_requisitado = ? AND data_entrega IS NULL",
+    let loans = sqlx::query_as::<_, OverdueLoan>(
+        "SELECT requisicoes.id, leitores.id AS id_leitor, leitores.nome AS leitor, livros.id AS id_livro, livros.nome AS livro,
+            exemplares.id AS id_exemplar, exemplares.codigo AS exemplar, requisicoes.data_requisicao, requisicoes.data_limite,
+            CAST(DATEDIFF(NOW(), requisicoes.data_limite) AS SIGNED) AS dias_atraso
+        FROM requisicoes
+        INNER JOIN leitores ON leitores.id = requisicoes.id_leitor
+        INNER JOIN livros ON livros.id = requisicoes.id_livro_requisitado
+        LEFT JOIN exemplares ON exemplares.id = requisicoes.id_exemplar
+        WHERE requisicoes.data_entrega IS NULL AND requisicoes.data_limite < NOW()
+        ORDER BY requisicoes.data_limite",
     )
-    .bind(book_id)
     .fetch_all(pool)
     .await
     .map_err(|e| {
-        tracing::error!("Falha ao consultar: {}", e);
-        format!("Falha ao consultar: {}", e)
+        tracing::error!("Falha ao consultar requisições em atraso: {}", e);
+        format!("Falha ao consultar requisições em atraso: {}", e)
     })?;
 
-    if request.is_empty() {
-        return Err("Requisição não encontrada".to_string());
-    }
+    Ok(loans)
+}
 
-    sqlx::query("UPDATE requisicoes SET data_entrega = NOW() WHERE id_livro_requisitado = ? AND data_entrega IS NULL")
-        .bind(book_id)
-        .execute(pool)
+#[tauri::command]
+pub async fn renew_loan(
+    token: String,
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<chrono::DateTime<chrono::Utc>, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_requisicao", pool)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao devolver livro: {}", e);
-            format!("Falha ao devolver livro: {}", e)
+            tracing::error!("{}", e);
+            e
         })?;
 
-    Ok(())
+    let librarian_id = get_from_jwt(&token)?
+        .get("id")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or("token inválido")?;
+
+    let (book_id, reader_id): (i32, i32) =
+        sqlx::query_as("SELECT id_livro_requisitado, id_leitor FROM requisicoes WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar requisição: {}", e);
+                format!("Falha ao consultar requisição: {}", e)
+            })?
+            .ok_or(LoanError::LoanNotFound)?;
+
+    expire_holds(pool).await?;
+
+    let loan_days = loan_period_days(book_id, reader_id, pool).await?;
+    let max_renewals = renewal_limit(reader_id, pool).await?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
+    })?;
+
+    let before =
+        snapshot::<Requisicao>("SELECT * FROM requisicoes WHERE id = ?", id, &mut tx).await?;
+
+    let due_date = extend_loan(id, librarian_id, loan_days, max_renewals, &mut tx).await?;
+
+    let after =
+        snapshot::<Requisicao>("SELECT * FROM requisicoes WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "requisicoes",
+        id,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
+    Ok(due_date)
 }
 
 #[tauri::command]
-pub async fn get_requests(
+pub async fn get_loan_history(
     token: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Requisicao>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    id: i32,
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<HistoricoRequisicao>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
-    let requests = sqlx::query_as::<_, Requisicao>("SELECT * FROM requisicoes")
-        .fetch_all(pool)
+    let history = sqlx::query_as::<_, HistoricoRequisicao>(
+        "SELECT historico_requisicoes.*, bibliotecarios.nome AS bibliotecario FROM historico_requisicoes
+        LEFT JOIN bibliotecarios ON bibliotecarios.id = historico_requisicoes.id_bibliotecario
+        WHERE historico_requisicoes.id_requisicao = ?
+        ORDER BY historico_requisicoes.data, historico_requisicoes.id",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar histórico da requisição: {}", e);
+        format!("Falha ao consultar histórico da requisição: {}", e)
+    })?;
+
+    Ok(history)
+}
+
+async fn barcode_transaction(id: i32, pool: &Pool<MySql>) -> Result<BarcodeTransaction, String> {
+    sqlx::query_as::<_, BarcodeTransaction>(
+        "SELECT requisicoes.id, leitores.id AS id_leitor, leitores.nome AS leitor, livros.id AS id_livro, livros.nome AS livro,
+            exemplares.codigo AS exemplar, requisicoes.data_limite
+        FROM requisicoes
+        INNER JOIN leitores ON leitores.id = requisicoes.id_leitor
+        INNER JOIN livros ON livros.id = requisicoes.id_livro_requisitado
+        LEFT JOIN exemplares ON exemplares.id = requisicoes.id_exemplar
+        WHERE requisicoes.id = ?",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar requisição: {}", e);
+        format!("Falha ao consultar requisição: {}", e)
+    })
+}
+
+async fn copy_by_barcode(code: &str, pool: &Pool<MySql>) -> Result<(i32, i32), String> {
+    let code = validate_barcode(code)?;
+
+    sqlx::query_as("SELECT id, id_livro FROM exemplares WHERE codigo = ?")
+        .bind(&code)
+        .fetch_optional(pool)
         .await
         .map_err(|e| {
-            tracing::error!("Falha ao consultar requisições: {}", e);
-            format!("Falha ao consultar requisições: {}", e)
+            tracing::error!("Falha ao consultar exemplar: {}", e);
+            format!("Falha ao consultar exemplar: {}", e)
+        })?
+        .ok_or_else(|| format!("Nenhum exemplar tem o código {}", code))
+}
+
+#[tauri::command]
+pub async fn checkout_by_barcode(
+    token: String,
+    card_number: String,
+    copy_code: String,
+    state: tauri::State<'_, Database>,
+) -> Result<BarcodeTransaction, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "criar_requisicao", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
+
+    let card_number = validate_barcode(&card_number)?;
+
+    let reader_id: i32 = sqlx::query_scalar("SELECT id FROM leitores WHERE numero_cartao = ?")
+        .bind(&card_number)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Falha ao consultar leitor: {}", e);
+            format!("Falha ao consultar leitor: {}", e)
+        })?
+        .ok_or_else(|| format!("Nenhum leitor tem o cartão {}", card_number))?;
+
+    let (copy_id, book_id) = copy_by_barcode(&copy_code, pool).await?;
+
+    let id = checkout(&token, book_id, reader_id, Some(copy_id), pool).await?;
+
+    Ok(barcode_transaction(id, pool).await?)
+}
+
+#[tauri::command]
+pub async fn return_by_barcode(
+    token: String,
+    copy_code: String,
+    state: tauri::State<'_, Database>,
+) -> Result<BarcodeTransaction, CommandError> {
+    let pool = &state.pool().await?;
+
+    verify_permission(&token, "atualizar_requisicao", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
         })?;
 
-    Ok(requests)
-}
\ No newline at end of file
+    let (copy_id, _) = copy_by_barcode(&copy_code, pool).await?;
+
+    let id: i32 = sqlx::query_scalar(
+        "SELECT id FROM requisicoes WHERE id_exemplar = ? AND data_entrega IS NULL",
+    )
+    .bind(copy_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar requisição: {}", e);
+        format!("Falha ao consultar requisição: {}", e)
+    })?
+    .ok_or("Este exemplar não está requisitado")?;
+
+    let fine = check_in(&token, id, None, false, pool).await?;
+
+    let mut transaction = barcode_transaction(id, pool).await?;
+    transaction.multa = fine;
+
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_bounds_clamp_the_limit() {
+        assert_eq!(page_bounds(0, 0), Ok((1, 0)));
+        assert_eq!(page_bounds(25, 50), Ok((25, 50)));
+        assert_eq!(page_bounds(10_000, 0), Ok((MAX_PAGE_SIZE, 0)));
+    }
+
+    #[test]
+    fn page_bounds_reject_negative_values() {
+        assert!(page_bounds(-1, 0).is_err());
+        assert!(page_bounds(10, -1).is_err());
+    }
+
+    #[test]
+    fn contains_pattern_escapes_wildcards() {
+        assert_eq!(contains_pattern("rosa"), "%rosa%");
+        assert_eq!(contains_pattern("100%_\\"), "%100\\%\\_\\\\%");
+    }
+}
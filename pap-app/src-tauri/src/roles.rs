@@ -1,23 +1,52 @@
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use serde_json::Value;
+use sqlx::MySqlConnection;
+
+use crate::{
+    audit::{record_audit, CREATE, DELETE, UPDATE},
+    db_structs::Cargo,
+    errors::CommandError,
+    jwt::{revoke_role_sessions, verify_jwt},
+    permissions::{verify_permission, AuthError},
+    Database,
+};
+
+// roles are one row per permission, so the audit stores them as a name and a list
+pub async fn role_snapshot(
+    role: &str,
+    conn: &mut MySqlConnection,
+) -> Result<Option<Value>, String> {
+    let permissions: Vec<i32> = sqlx::query_scalar(
+        "SELECT permissao FROM cargos WHERE nome = ? ORDER BY permissao FOR UPDATE",
+    )
+    .bind(role)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao consultar cargo: {}", e);
+        format!("Falha ao consultar cargo: {}", e)
+    })?;
 
-use crate::{db_structs::Cargo, jwt::verify_jwt, Database};
+    if permissions.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::json!({
+        "nome": role,
+        "permissoes": permissions,
+    })))
+}
 
 #[tauri::command]
 pub async fn get_roles(
     token: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<Cargo>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<Cargo>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let roles = sqlx::query_as::<_, Cargo>("SELECT nome, permissao FROM cargos")
@@ -36,23 +65,25 @@ pub async fn create_role(
     token: String,
     role: String,
     permissions: Vec<i32>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "criar_cargo", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
     let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM cargos WHERE nome = ?")
         .bind(&role)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
@@ -60,7 +91,7 @@ pub async fn create_role(
         })?;
 
     if count > 0 {
-        return Err("Cargo já existe".to_string());
+        return Err("Cargo já existe".to_string().into());
     }
 
     for permission in permissions {
@@ -68,8 +99,8 @@ pub async fn create_role(
             "SELECT COUNT(*) FROM cargos WHERE nome = ? AND permissao = ?",
         )
         .bind(&role)
-        .bind(&permission)
-        .fetch_one(pool)
+        .bind(permission)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
@@ -83,7 +114,7 @@ pub async fn create_role(
         sqlx::query("INSERT INTO cargos (nome, permissao) VALUES (?, ?)")
             .bind(&role)
             .bind(permission)
-            .execute(pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao inserir cargo: {}", e);
@@ -91,6 +122,15 @@ pub async fn create_role(
             })?;
     }
 
+    let after = role_snapshot(&role, &mut tx).await?;
+
+    record_audit(Some(&token), CREATE, "cargos", &role, None, after, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -100,23 +140,31 @@ pub async fn update_role(
     role: String,
     new_role_name: Option<String>,
     permissions: Vec<i32>,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "atualizar_cargo", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before = role_snapshot(&role, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Cargo não existe".to_string().into());
+    }
+
     sqlx::query("DELETE FROM cargos WHERE nome = ?")
         .bind(&role)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao excluir cargo: {}", e);
@@ -128,8 +176,8 @@ pub async fn update_role(
             "SELECT COUNT(*) FROM cargos WHERE nome = ? AND permissao = ?",
         )
         .bind(&role)
-        .bind(&permission)
-        .fetch_one(pool)
+        .bind(permission)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
@@ -137,13 +185,13 @@ pub async fn update_role(
         })?;
 
         if count > 0 {
-            return Err("Cargo já possui permissão".to_string());
+            return Err("Cargo já possui permissão".to_string().into());
         }
 
         sqlx::query("INSERT INTO cargos (nome, permissao) VALUES (?, ?)")
             .bind(&role)
             .bind(permission)
-            .execute(pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao inserir cargo: {}", e);
@@ -151,11 +199,13 @@ pub async fn update_role(
             })?;
     }
 
-    if new_role_name.is_some() {
+    revoke_role_sessions(&role, &mut tx).await?;
+
+    if let Some(new_role_name) = &new_role_name {
         sqlx::query("UPDATE cargos SET nome = ? WHERE nome = ?")
             .bind(&role)
-            .bind(&new_role_name.unwrap())
-            .execute(pool)
+            .bind(new_role_name)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 tracing::error!("Falha ao atualizar cargo: {}", e);
@@ -163,6 +213,24 @@ pub async fn update_role(
             })?;
     }
 
+    let after = role_snapshot(&role, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        UPDATE,
+        "cargos",
+        &role,
+        before,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -170,23 +238,25 @@ pub async fn update_role(
 pub async fn delete_role(
     token: String,
     role: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "apagar_cargo", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
     let count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM cargos WHERE nome = ?")
         .bind(&role)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao consultar: {}", e);
@@ -194,27 +264,38 @@ pub async fn delete_role(
         })?;
 
     if count == 0 {
-        return Err("Cargo não existe".to_string());
+        return Err("Cargo não existe".to_string().into());
     }
 
+    let before = role_snapshot(&role, &mut tx).await?;
+
     sqlx::query("DELETE FROM cargos WHERE nome = ?")
         .bind(&role)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao excluir cargo: {}", e);
             format!("Falha ao excluir cargo: {}", e)
         })?;
 
+    revoke_role_sessions(&role, &mut tx).await?;
+
     sqlx::query("UPDATE bibliotecarios SET cargo = NULL WHERE cargo = ?")
         .bind(&role)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao atualizar bibliotecário: {}", e);
             format!("Falha ao atualizar bibliotecário: {}", e)
         })?;
 
+    record_audit(Some(&token), DELETE, "cargos", &role, before, None, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -228,18 +309,13 @@ pub struct RoleResponse {
 pub async fn get_role_by_name(
     token: String,
     role: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<RoleResponse, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<RoleResponse, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let roles = sqlx::query_as::<_, Cargo>("SELECT nome, permissao FROM cargos WHERE nome = ?")
@@ -0,0 +1,46 @@
+use std::str::FromStr;
+
+use sqlx::{MySql, Pool};
+
+pub async fn get_setting<T: FromStr>(
+    key: &str,
+    default: T,
+    conn: &Pool<MySql>,
+) -> Result<T, String> {
+    let value: Option<String> =
+        sqlx::query_scalar("SELECT valor FROM configuracoes WHERE chave = ?")
+            .bind(key)
+            .fetch_optional(conn)
+            .await
+            .map_err(|e| {
+                tracing::error!("Falha ao consultar configuração: {}", e);
+                format!("Falha ao consultar configuração: {}", e)
+            })?;
+
+    match value {
+        Some(value) => value
+            .parse::<T>()
+            .map_err(|_| format!("Configuração {} inválida", key)),
+        None => Ok(default),
+    }
+}
+
+pub async fn set_setting<T: ToString>(
+    key: &str,
+    value: T,
+    conn: &Pool<MySql>,
+) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO configuracoes (chave, valor) VALUES (?, ?) ON DUPLICATE KEY UPDATE valor = VALUES(valor)",
+    )
+    .bind(key)
+    .bind(value.to_string())
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Falha ao guardar configuração: {}", e);
+        format!("Falha ao guardar configuração: {}", e)
+    })?;
+
+    Ok(())
+}
@@ -1,22 +1,22 @@
-use tokio::sync::Mutex;
-
-use crate::{db_structs::SubCategoria, jwt::verify_jwt, Database};
+use crate::{
+    audit::{record_audit, snapshot, CREATE, DELETE},
+    db_structs::SubCategoria,
+    errors::CommandError,
+    jwt::verify_jwt,
+    permissions::{verify_permission, AuthError},
+    Database,
+};
 
 #[tauri::command]
 pub async fn get_sub_categories(
     token: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<SubCategoria>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<SubCategoria>, CommandError> {
+    let pool = &state.pool().await?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
+    verify_jwt(&token, pool).await.map_err(|e| {
         tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+        AuthError::from_token_error(e)
     })?;
 
     let sub_categories = sqlx::query_as::<_, SubCategoria>("SELECT * FROM sub_categorias")
@@ -33,14 +33,9 @@ pub async fn get_sub_categories(
 #[tauri::command]
 pub async fn get_sub_categories_by_name(
     name: String,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<Vec<SubCategoria>, String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
-
-    let pool = &db.pool;
+    state: tauri::State<'_, Database>,
+) -> Result<Vec<SubCategoria>, CommandError> {
+    let pool = &state.pool().await?;
 
     let sub_categories = sqlx::query_as::<_, SubCategoria>(
         "SELECT * FROM sub_categorias WHERE LOWER(nome) LIKE LOWER(?)",
@@ -60,25 +55,34 @@ pub async fn get_sub_categories_by_name(
 pub async fn delete_sub_category(
     token: String,
     id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "apagar_sub_categoria", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
+    let before =
+        snapshot::<SubCategoria>("SELECT * FROM sub_categorias WHERE id = ?", id, &mut tx).await?;
+
+    if before.is_none() {
+        return Err("Sub-categoria não encontrada".to_string().into());
+    }
+
     let used = sqlx::query_scalar::<_, bool>(
         "SELECT EXISTS(SELECT * FROM livros WHERE id_sub_categoria = ?)",
     )
     .bind(id)
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         tracing::error!("Falha ao consultar se sub-categoria é usada: {}", e);
@@ -87,19 +91,37 @@ pub async fn delete_sub_category(
 
     if used {
         return Err(
-            "Esta sub-categoria tem livros associados, não é possível apagá-la".to_string(),
+            "Esta sub-categoria tem livros associados, não é possível apagá-la"
+                .to_string()
+                .into(),
         );
     }
 
     sqlx::query("DELETE FROM sub_categorias WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao deletar sub-categoria: {}", e);
             format!("Falha ao deletar sub-categoria: {}", e)
         })?;
 
+    record_audit(
+        Some(&token),
+        DELETE,
+        "sub_categorias",
+        id,
+        before,
+        None,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
 
@@ -108,18 +130,20 @@ pub async fn create_sub_category(
     token: String,
     name: String,
     category_id: i32,
-    state: tauri::State<'_, Mutex<Option<Database>>>,
-) -> Result<(), String> {
-    let state_lock = state.lock().await;
-    let db = state_lock
-        .as_ref()
-        .ok_or("Base de dados não inicializada")?;
+    state: tauri::State<'_, Database>,
+) -> Result<(), CommandError> {
+    let pool = &state.pool().await?;
 
-    let pool = &db.pool;
+    verify_permission(&token, "criar_sub_categoria", pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("{}", e);
+            e
+        })?;
 
-    verify_jwt(&token, &pool).await.map_err(|e| {
-        tracing::error!("Falha ao verificar token: {}", e);
-        format!("Falha ao verificar token: {}", e)
+    let mut tx = pool.begin().await.map_err(|e| {
+        tracing::error!("Falha ao iniciar transação: {}", e);
+        format!("Falha ao iniciar transação: {}", e)
     })?;
 
     let exists = sqlx::query_scalar::<_, bool>(
@@ -127,7 +151,7 @@ pub async fn create_sub_category(
     )
     .bind(&name)
     .bind(category_id)
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         tracing::error!("Falha ao consultar se sub-categoria já existe: {}", e);
@@ -135,18 +159,38 @@ pub async fn create_sub_category(
     })?;
 
     if exists {
-        return Err("Esta sub-categoria já existe".to_string());
+        return Err("Esta sub-categoria já existe".to_string().into());
     }
 
-    sqlx::query("INSERT INTO sub_categorias (nome, id_categoria) VALUES (?, ?)")
+    let result = sqlx::query("INSERT INTO sub_categorias (nome, id_categoria) VALUES (?, ?)")
         .bind(&name)
         .bind(category_id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Falha ao inserir sub-categoria: {}", e);
             format!("Falha ao inserir sub-categoria: {}", e)
         })?;
 
+    let id = result.last_insert_id() as i32;
+    let after =
+        snapshot::<SubCategoria>("SELECT * FROM sub_categorias WHERE id = ?", id, &mut tx).await?;
+
+    record_audit(
+        Some(&token),
+        CREATE,
+        "sub_categorias",
+        id,
+        None,
+        after,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await.map_err(|e| {
+        tracing::error!("Falha ao confirmar transação: {}", e);
+        format!("Falha ao confirmar transação: {}", e)
+    })?;
+
     Ok(())
 }
@@ -1,8 +1,45 @@
 import { invoke } from "@tauri-apps/api";
-import { dbStringStore, jwtStore } from "./stores";
-import { DatabaseConnectionStatus } from "./types";
+import { dbStringStore, jwtStore, refreshTokenStore } from "./stores";
+import {
+  DatabaseConnectionStatus,
+  type CommandError,
+  type Sessao,
+} from "./types";
 import { goto } from "$app/navigation";
 
+export function isCommandError(error: unknown): error is CommandError {
+  return typeof error === "object" && error !== null && "kind" in error;
+}
+
+export function errorMessage(error: unknown): string {
+  return isCommandError(error) ? error.message : String(error);
+}
+
+// concurrent calls share one refresh, a second request with the same refresh token would revoke the session
+let refreshing: Promise<string> | null = null;
+
+async function renewToken(expired: string): Promise<string> {
+  // another call already replaced the token this one was sent with
+  if (jwtStore.get() !== expired) return jwtStore.get();
+
+  if (!refreshing) {
+    refreshing = invoke<Sessao>("refresh_token", {
+      refreshToken: refreshTokenStore.get(),
+    })
+      .then((session) => {
+        jwtStore.set(session.token!);
+        refreshTokenStore.set(session.refresh_token!);
+
+        return session.token!;
+      })
+      .finally(() => {
+        refreshing = null;
+      });
+  }
+
+  return refreshing;
+}
+
 export async function call<T>(method: string, args: any = {}): Promise<T> {
   let sendSetup = false;
   if (dbStringStore.get().dbUrl === "") goto("/setup");
@@ -11,7 +48,31 @@ export async function call<T>(method: string, args: any = {}): Promise<T> {
   while (true) {
     switch (dbStringStore.getProperty<DatabaseConnectionStatus>("status")) {
       case DatabaseConnectionStatus.CONNECTED:
-        return await invoke(method, args);
+        try {
+          return await invoke(method, args);
+        } catch (error) {
+          if (
+            isCommandError(error) &&
+            error.kind === "ExpiredToken" &&
+            refreshTokenStore.get() !== ""
+          ) {
+            let token: string;
+
+            try {
+              token = await renewToken(args.token);
+            } catch (refreshError) {
+              jwtStore.set("");
+              refreshTokenStore.set("");
+              goto("/login");
+
+              throw refreshError;
+            }
+
+            return await invoke(method, { ...args, token });
+          }
+
+          throw error;
+        }
       case DatabaseConnectionStatus.CONNECTING:
         await new Promise((resolve) => setTimeout(resolve, 1000));
         break;
@@ -72,7 +72,7 @@ function safeParseJSON<T>(json: string | null, fallback: T): T {
 }
 
 const dbStringStore = new Store(
-  safeParseJSON<{ dbUrl: string; makeTables?: boolean }>(
+  safeParseJSON<{ dbUrl: string; makeTables?: boolean; poolSize?: number }>(
     localStorage.getItem("dbUrl"),
     { dbUrl: "", makeTables: false }
   ),
@@ -96,6 +96,7 @@ dbStringStore.setCallback(async (value) => {
     await invoke("init", {
       dbUrl: value.dbUrl,
       makeTables: value.makeTables,
+      poolSize: value.poolSize,
     });
 
     toast.success("Connectado com sucesso à base de dados");
@@ -106,12 +107,16 @@ dbStringStore.setCallback(async (value) => {
   }
 });
 
-const jwtStore = new Store("");
-
-const loanPeriodStore = new Store(
-  safeParseJSON<number>(localStorage.getItem("loanPeriod"), 7),
+// generated on the first run so login lockouts are per install instead of per host
+const clientIdStore = new Store(
+  safeParseJSON<string | null>(localStorage.getItem("clientId"), null) ??
+    crypto.randomUUID(),
   true,
-  "loanPeriod"
+  "clientId"
 );
 
-export { dbStringStore, jwtStore, loanPeriodStore };
+const jwtStore = new Store("");
+
+const refreshTokenStore = new Store("");
+
+export { dbStringStore, clientIdStore, jwtStore, refreshTokenStore };
@@ -6,12 +6,54 @@ export interface Livro {
   idioma: string;
   img_url?: string;
   ano_edicao?: string;
-  autor?: string;
-  autor_id?: number;
+  isbn10?: string;
+  isbn13?: string;
+  autores: AutorLivro[];
   editora: string;
   categoria?: string;
   sub_categoria?: string;
   requisitado: boolean;
+  exemplares: number;
+  exemplares_disponiveis: number;
+}
+
+export interface AutorLivro {
+  id: number;
+  nome: string;
+  papel: string;
+}
+
+export interface LivroCriado {
+  livro: Livro;
+  duplicado: boolean;
+}
+
+export interface Exemplar {
+  id: number;
+  id_livro: number;
+  codigo?: string;
+  localizacao?: string;
+  estado: string;
+  data_aquisicao?: string;
+  requisitado: boolean;
+}
+
+export interface Sessao {
+  token: string | null;
+  refresh_token: string | null;
+  must_change_password: boolean;
+  must_enroll_totp: boolean;
+  totp_challenge: string | null;
+}
+
+export interface EstadoDoisFatores {
+  enabled: boolean;
+  required: boolean;
+}
+
+export interface ConfiguracaoDoisFatores {
+  secret: string;
+  uri: string;
 }
 
 export interface bibliotecario {
@@ -26,6 +68,16 @@ export interface Leitor {
   morada: string;
   telefone: number;
   email: string;
+  id_tipo: number;
+  numero_cartao?: string;
+}
+
+export interface TipoLeitor {
+  id: number;
+  nome: string;
+  max_requisicoes?: number | null;
+  dias_emprestimo?: number | null;
+  max_renovacoes?: number | null;
 }
 
 export interface Categoria {
@@ -57,8 +109,143 @@ export interface Requisicao {
   id: number;
   id_leitor: number;
   id_livro_requisitado: number;
+  id_exemplar?: number;
+  data_requisicao: string;
+  data_limite?: string;
+  renovacoes: number;
+  data_entrega?: string;
+  notas_devolucao?: string;
+  danificado: boolean;
+}
+
+export interface RequisicaoDetalhada {
+  id: number;
+  id_leitor: number;
+  leitor: string;
+  id_livro: number;
+  livro: string;
+  id_exemplar?: number;
+  exemplar?: string;
   data_requisicao: string;
+  data_limite?: string;
   data_entrega?: string;
+  renovacoes: number;
+  notas_devolucao?: string;
+  danificado: boolean;
+  dias_atraso?: number;
+  multa: number;
+}
+
+export interface FiltroRequisicoes {
+  status?: "aberta" | "devolvida" | "atrasada";
+  reader_id?: number;
+  book_id?: number;
+  from?: string;
+  to?: string;
+  sort?: "data_requisicao" | "data_limite" | "data_entrega" | "leitor" | "livro";
+  descending?: boolean;
+}
+
+export interface PaginaRequisicoes {
+  requisicoes: RequisicaoDetalhada[];
+  total: number;
+}
+
+export interface RequisicoesLivro {
+  livro: Livro;
+  abertas: RequisicaoDetalhada[];
+  historico: RequisicaoDetalhada[];
+}
+
+export interface Reserva {
+  id: number;
+  id_livro: number;
+  livro: string;
+  id_leitor: number;
+  leitor: string;
+  estado: "pendente" | "disponivel" | "levantada" | "cancelada" | "expirada";
+  posicao?: number;
+  data_reserva: string;
+  data_disponivel?: string;
+  data_expiracao?: string;
+}
+
+export interface HistoricoRequisicao {
+  id: number;
+  id_requisicao: number;
+  id_bibliotecario?: number;
+  bibliotecario?: string;
+  evento: string;
+  data_limite_anterior?: string;
+  data_limite?: string;
+  data: string;
+}
+
+export interface RequisicaoEmAtraso {
+  id: number;
+  id_leitor: number;
+  leitor: string;
+  id_livro: number;
+  livro: string;
+  id_exemplar?: number;
+  exemplar?: string;
+  data_requisicao: string;
+  data_limite: string;
+  dias_atraso: number;
+}
+
+export interface TransacaoCodigoBarras {
+  id: number;
+  id_leitor: number;
+  leitor: string;
+  id_livro: number;
+  livro: string;
+  exemplar?: string;
+  data_limite?: string;
+  multa: number;
+}
+
+export interface PoliticaEmprestimos {
+  loan_days: number;
+  max_renewals: number;
+  hold_pickup_days: number;
+}
+
+export interface PoliticaMultas {
+  daily_rate: number;
+  grace_days: number;
+  cap: number;
+  block_threshold: number;
+}
+
+export interface MovimentoLeitor {
+  id: number;
+  id_leitor: number;
+  id_requisicao?: number;
+  id_bibliotecario?: number;
+  tipo: "multa" | "pagamento" | "perdao";
+  valor: number;
+  descricao?: string;
+  data: string;
+}
+
+export interface PrazoCategoria {
+  id_categoria: number;
+  categoria: string;
+  dias: number;
+}
+
+export interface GrelhaEtiquetas {
+  page_width: number;
+  page_height: number;
+  columns: number;
+  rows: number;
+  margin_top: number;
+  margin_left: number;
+  label_width: number;
+  label_height: number;
+  gap_x: number;
+  gap_y: number;
 }
 
 export interface Editora {
@@ -75,6 +262,18 @@ export interface Cargo {
   permissao: number;
 }
 
+export interface MigracaoPendente {
+  versao: number;
+  nome: string;
+}
+
+// what commands reject with, kind and detail come from the typed errors of the backend
+export interface CommandError {
+  kind: string;
+  detail?: any;
+  message: string;
+}
+
 export enum DatabaseConnectionStatus {
   NOT_CONNECTED,
   CONNECTING,
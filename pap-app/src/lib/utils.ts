@@ -82,3 +82,18 @@ export async function hasLibrariansPageAccess() {
 
   return true;
 }
+
+// amounts come from the backend in cents
+export function formatMoney(cents: number) {
+  return (cents / 100).toLocaleString("pt-PT", {
+    style: "currency",
+    currency: "EUR",
+  });
+}
+
+export const authorRoles: Record<string, string> = {
+  autor: "Autor",
+  tradutor: "Tradutor",
+  ilustrador: "Ilustrador",
+  editor: "Editor",
+};